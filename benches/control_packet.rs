@@ -0,0 +1,243 @@
+use bytes::BytesMut;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use ds::bench::ControlEncoder;
+use ds::JoystickValue;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts allocations, so that the allocations made for each packet can be reported alongside the timings
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Returns the number of allocations made by `f`, after it has run once to warm up any buffers
+fn allocations(mut f: impl FnMut()) -> usize {
+    f();
+    let start = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - start
+}
+
+/// Encodes control packets the way they were before the encoder wrote into a reused buffer, as a baseline
+///
+/// Every packet builds its joysticks from `Vec`s, clones the queued tags into boxed trait objects, and constructs each
+/// tag into a `Vec` of its own before copying it into the `Vec` that is sent.
+struct LegacyControlEncoder {
+    joysticks: Vec<Vec<JoystickValue>>,
+    pending: Vec<LegacyJoysticks>,
+    seqnum: u16,
+}
+
+trait LegacyTag {
+    fn id(&self) -> u8;
+
+    fn data(&self) -> Vec<u8>;
+
+    fn construct(&self) -> Vec<u8> {
+        let mut buf = vec![self.id()];
+        buf.extend(self.data());
+        buf.insert(0, buf.len() as u8);
+
+        buf
+    }
+}
+
+#[derive(Clone)]
+struct LegacyJoysticks {
+    axes: Vec<i8>,
+    buttons: Vec<bool>,
+    povs: Vec<i16>,
+}
+
+impl LegacyTag for LegacyJoysticks {
+    fn id(&self) -> u8 {
+        0x0c
+    }
+
+    fn data(&self) -> Vec<u8> {
+        let mut buf = vec![self.axes.len() as u8];
+        buf.extend(self.axes.iter().map(|&axis| axis as u8));
+
+        let mut buttons = Vec::new();
+        for chunk in self.buttons.chunks(8) {
+            let byte = chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (i, &pressed)| byte | (pressed as u8) << i);
+            buttons.push(byte);
+        }
+        buf.push(self.buttons.len() as u8);
+        buf.extend(buttons.into_iter().rev());
+
+        buf.push(self.povs.len() as u8);
+        for pov in &self.povs {
+            buf.extend_from_slice(&pov.to_be_bytes());
+        }
+
+        buf
+    }
+}
+
+impl LegacyControlEncoder {
+    /// Creates an encoder that takes a clone of `joysticks` for every packet, like `ControlEncoder`
+    fn new(joysticks: Vec<Vec<JoystickValue>>) -> LegacyControlEncoder {
+        LegacyControlEncoder {
+            joysticks,
+            pending: Vec::new(),
+            seqnum: 0,
+        }
+    }
+
+    /// Encodes the next control packet into a newly allocated `Vec`
+    fn encode(&mut self) -> Vec<u8> {
+        for joystick in self.joysticks.clone() {
+            let mut axes = vec![0; 6];
+            let mut buttons = vec![false; 10];
+            let mut povs = vec![-1i16; 1];
+
+            for value in joystick {
+                match value {
+                    JoystickValue::Axis { id, value } => {
+                        let id = id as usize;
+                        if axes.len() <= id {
+                            axes.resize(id + 1, 0);
+                        }
+                        axes[id] = (value * 128f32) as i8;
+                    }
+                    JoystickValue::Button { id, pressed } => {
+                        let id = id as usize;
+                        if buttons.len() < id {
+                            buttons.resize(id, false);
+                        }
+                        buttons[id - 1] = pressed;
+                    }
+                    JoystickValue::POV { id, angle } => {
+                        let id = id as usize;
+                        if povs.len() <= id {
+                            povs.resize(id + 1, -1);
+                        }
+                        povs[id] = angle;
+                    }
+                }
+            }
+            self.pending.push(LegacyJoysticks {
+                axes,
+                buttons,
+                povs,
+            });
+        }
+
+        let mut tags: Vec<Box<dyn LegacyTag>> = Vec::new();
+        for tag in self.pending.clone() {
+            tags.push(Box::new(tag));
+        }
+        self.pending.clear();
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&self.seqnum.to_be_bytes());
+        buf.extend_from_slice(&[0x01, 0x00, 0x00, 0x00]);
+        for tag in &tags {
+            buf.extend(tag.construct());
+        }
+        self.seqnum = self.seqnum.wrapping_add(1);
+
+        buf
+    }
+}
+
+/// Returns `count` joysticks, each with every axis, button and POV in use
+fn full_joysticks(count: usize) -> Vec<Vec<JoystickValue>> {
+    let mut joystick = Vec::new();
+    for id in 0..12 {
+        joystick.push(JoystickValue::Axis { id, value: 0.5 });
+    }
+    for id in 1..=32 {
+        joystick.push(JoystickValue::Button {
+            id,
+            pressed: id % 2 == 0,
+        });
+    }
+    for id in 0..4 {
+        joystick.push(JoystickValue::POV { id, angle: 90 });
+    }
+
+    vec![joystick; count]
+}
+
+/// Compares encoding into a buffer that is reused between packets, as the UDP task does, with allocating a fresh
+/// buffer for every packet and with the boxed tags and per-tag `Vec`s that were used before
+fn control_packet(c: &mut Criterion) {
+    let mut group = c.benchmark_group("control_packet");
+
+    for &count in &[0, 6] {
+        let joysticks = full_joysticks(count);
+
+        // The supplier returns its joysticks by value, so cloning them is counted separately from the encoders
+        let supplier = allocations(|| {
+            black_box(joysticks.clone());
+        });
+        let mut encoder = ControlEncoder::new(joysticks.clone());
+        let mut buf = BytesMut::with_capacity(1024);
+        let reused = allocations(|| {
+            buf.clear();
+            encoder.encode(&mut buf);
+        }) - supplier;
+        let mut legacy = LegacyControlEncoder::new(joysticks.clone());
+        let boxed = allocations(|| {
+            black_box(legacy.encode());
+        }) - supplier;
+        println!(
+            "control_packet/{}_joysticks: {} allocations per packet with a reused buffer, {} with boxed tags, \
+             not counting the {} made by the joystick supplier",
+            count, reused, boxed, supplier
+        );
+
+        group.bench_function(format!("reused_buffer/{}_joysticks", count), |b| {
+            let mut encoder = ControlEncoder::new(joysticks.clone());
+            let mut buf = BytesMut::with_capacity(1024);
+            b.iter(|| {
+                buf.clear();
+                encoder.encode(&mut buf);
+                black_box(&buf);
+            })
+        });
+
+        group.bench_function(format!("new_buffer/{}_joysticks", count), |b| {
+            let mut encoder = ControlEncoder::new(joysticks.clone());
+            b.iter(|| {
+                let mut buf = BytesMut::new();
+                encoder.encode(&mut buf);
+                black_box(buf)
+            })
+        });
+
+        group.bench_function(format!("boxed_tags/{}_joysticks", count), |b| {
+            let mut encoder = LegacyControlEncoder::new(joysticks.clone());
+            b.iter(|| black_box(encoder.encode()))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, control_packet);
+criterion_main!(benches);
@@ -0,0 +1,26 @@
+//! Entry points for the benchmarks in `benches/`. This module is not part of the public API.
+
+use crate::ds::state::send::SendState;
+use crate::{Alliance, JoystickValue};
+use bytes::BytesMut;
+
+/// Encodes control packets the same way that the UDP task does every 20ms
+pub struct ControlEncoder {
+    state: SendState,
+}
+
+impl ControlEncoder {
+    /// Creates an encoder with a joystick supplier that returns a clone of `joysticks` for every packet
+    pub fn new(joysticks: Vec<Vec<JoystickValue>>) -> ControlEncoder {
+        let mut state = SendState::new(Alliance::new_red(1));
+        state.set_joystick_supplier(move || joysticks.clone());
+
+        ControlEncoder { state }
+    }
+
+    /// Encodes the next control packet to the end of `buf`
+    pub fn encode(&mut self, buf: &mut BytesMut) {
+        self.state.encode_control(buf);
+        self.state.increment_seqnum();
+    }
+}
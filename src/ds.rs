@@ -1,33 +1,36 @@
-use failure::bail;
-
-use std::thread;
-
+mod async_ds;
+mod builder;
 mod conn;
+mod router;
 pub(crate) mod state;
 
-use self::conn::*;
+pub use self::async_ds::AsyncDriverStation;
+pub use self::builder::DriverStationBuilder;
 use self::state::*;
 
 use futures::executor::block_on;
-use std::sync::Arc;
-
-use futures_channel::mpsc::{unbounded, UnboundedSender};
+use futures_channel::oneshot;
 
-use crate::proto::tcp::outbound::{GameData, TcpTag};
+use crate::proto::tcp::outbound::{JoystickDescriptor, TcpTag};
+use crate::proto::udp::inbound::types::tags::{
+    CanMetrics, CpuInfo, DiskInfo, JoystickOutput, PdpLog, RamInfo, UnknownTag,
+};
 use crate::proto::udp::inbound::types::Trace;
 use crate::proto::udp::outbound::types::tags::UdpTag;
 use crate::proto::udp::outbound::types::*;
 use crate::util::ip_from_team_number;
-use crate::{Result, TcpPacket};
+use crate::{DisableFaults, RailFaults, Result, TcpConnectionState, TcpPacket, VersionInfo};
 
 /// Represents a connection to the roboRIO acting as a driver station
 ///
 /// This struct will contain relevant functions to update the state of the robot,
 /// and also manages the threads that manage network connections and joysticks
+///
+/// Every method blocks the calling thread, so it shouldn't be used from async code. Use [`AsyncDriverStation`](struct.AsyncDriverStation.html) instead.
 pub struct DriverStation {
-    thread_tx: UnboundedSender<Signal>,
-    team_number: u32,
-    state: Arc<DsState>,
+    inner: AsyncDriverStation,
+    /// Shuts down the runtime that the tasks of `inner` run on when dropped, after they've been told to stop
+    _runtime_stop: oneshot::Sender<()>,
 }
 
 impl DriverStation {
@@ -35,168 +38,306 @@ impl DriverStation {
     ///
     /// This driver station will attempt to connect to a roboRIO at 10.TE.AM.2,
     /// if the roboRIO is at a different ip, use [new] and specify the ip directly.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sockets used to communicate with the roboRIO can't be bound.
+    /// Use [`builder`](#method.builder) to handle the error instead.
     pub fn new_team(team_number: u32, alliance: Alliance) -> DriverStation {
         Self::new(&ip_from_team_number(team_number), alliance, team_number)
     }
 
     /// Creates a new driver station for the given alliance station and team number
     /// Connects to the roborio at `ip`. To infer the ip from team_number, use `new_team` instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sockets used to communicate with the roboRIO can't be bound.
+    /// Use [`builder`](#method.builder) to handle the error instead.
     pub fn new(ip: &str, alliance: Alliance, team_number: u32) -> DriverStation {
-        // Channels to communicate to the threads that make up the application, used to break out of infinite loops when the struct is dropped
-        let (tx, rx) = unbounded::<Signal>();
-
-        // Global state of the driver station
-        let state = Arc::new(DsState::new(alliance));
-
-        // Thread containing UDP sockets communicating with the roboRIO
-        let udp_state = state.clone();
-        let udp_ip = ip.to_owned();
-
-        let sim_tx = tx.clone();
-        thread::spawn(move || {
-            use tokio::runtime::Runtime;
-            let mut rt = Runtime::new().unwrap();
-            rt.spawn(sim_conn(sim_tx));
-            rt.block_on(udp_conn(udp_state, udp_ip, rx))
-                .expect("Error with udp connection");
-        });
-
-        DriverStation {
-            thread_tx: tx,
-            state,
-            team_number,
-        }
+        DriverStation::builder(team_number, alliance)
+            .target_ip(ip)
+            .build()
+            .expect("Failed to start driver station")
+    }
+
+    /// Returns a builder for a driver station with the given team number and alliance
+    ///
+    /// The builder can change the ports, timings and network interface used to communicate with the roboRIO.
+    pub fn builder(team_number: u32, alliance: Alliance) -> DriverStationBuilder {
+        DriverStationBuilder::new(team_number, alliance)
     }
 
     /// Provides a closure that will be called when constructing outbound packets to append joystick values
+    ///
+    /// Each joystick is sent with the axis, button and POV counts from the descriptor for its port, or 6 axes, 10 buttons
+    /// and 1 POV before a descriptor is sent. Values past those counts grow them for all later packets.
     pub fn set_joystick_supplier(
         &mut self,
         supplier: impl Fn() -> Vec<Vec<JoystickValue>> + Send + Sync + 'static,
     ) {
-        block_on(self.state.send().lock()).set_joystick_supplier(supplier);
+        block_on(self.inner.set_joystick_supplier(supplier))
+    }
+
+    /// Provides a closure that will be called to describe the joysticks plugged into each port
+    ///
+    /// The descriptors are sent to the roboRIO when it connects and whenever they change, allowing robot code to
+    /// query joystick names and types. The `index` of each descriptor is set from its position. Ports that the joystick
+    /// supplier provides values for but the closure doesn't describe are sent a default descriptor, without a name and
+    /// with the counts that the port is sent with.
+    pub fn set_joystick_descriptor_supplier(
+        &mut self,
+        supplier: impl Fn() -> Vec<JoystickDescriptor> + Send + Sync + 'static,
+    ) {
+        block_on(self.inner.set_joystick_descriptor_supplier(supplier))
     }
 
     /// Provides a closure that will be called when TCP packets are received from the roboRIO
     ///
     /// Example usage: Logging all stdout messages from robot code.
     pub fn set_tcp_consumer(&mut self, consumer: impl FnMut(TcpPacket) + Send + Sync + 'static) {
-        block_on(self.state.tcp().lock()).set_tcp_consumer(consumer);
+        block_on(self.inner.set_tcp_consumer(consumer));
     }
 
     /// Changes the alliance for the given `DriverStation`
     pub fn set_alliance(&mut self, alliance: Alliance) {
-        block_on(self.state.send().lock()).set_alliance(alliance);
+        block_on(self.inner.set_alliance(alliance));
     }
 
     /// Changes the given `mode` the robot will be in
     pub fn set_mode(&mut self, mode: Mode) {
-        block_on(self.state.send().lock()).set_mode(mode);
+        block_on(self.inner.set_mode(mode));
     }
 
     pub fn ds_mode(&self) -> DsMode {
-        *block_on(self.state.send().lock()).ds_mode()
+        block_on(self.inner.ds_mode())
     }
 
     /// Changes the team number of this driver station, as well as the ip the driver station will attempt to connect to.
     /// The ip of the new roboRIO target is 10.TE.AM.2
     pub fn set_team_number(&mut self, team_number: u32) {
-        self.team_number = team_number;
-        self.thread_tx
-            .unbounded_send(Signal::NewTarget(ip_from_team_number(team_number)))
-            .unwrap();
+        self.inner.set_team_number(team_number);
     }
 
     pub fn set_use_usb(&mut self, use_usb: bool) {
-        if use_usb {
-            self.thread_tx
-                .unbounded_send(Signal::NewTarget("172.22.11.2".to_string()))
-                .unwrap();
-        } else {
-            self.thread_tx
-                .unbounded_send(Signal::NewTarget(ip_from_team_number(self.team_number)))
-                .unwrap();
-        }
+        self.inner.set_use_usb(use_usb);
     }
 
     pub fn team_number(&self) -> u32 {
-        self.team_number
+        self.inner.team_number()
     }
 
     /// Sets the game specific message sent to the robot, and used during the autonomous period
+    ///
+    /// The format of the message changes between seasons, it can be up to 255 bytes long.
     pub fn set_game_specific_message(&mut self, message: &str) -> Result<()> {
-        if message.len() != 3 {
-            bail!("Message should be 3 characters long");
-        }
+        block_on(self.inner.set_game_specific_message(message))
+    }
 
-        let _ = block_on(self.state.tcp().lock()).queue_tcp(TcpTag::GameData(GameData {
-            gsm: message.to_string(),
-        }));
-        Ok(())
+    /// Sets the IANA timezone, such as `America/Toronto`, that the roboRIO is set to alongside its clock
+    ///
+    /// By default the timezone is detected from the host. If it can't be detected, or `None` is given, only the time is
+    /// sent, in UTC.
+    ///
+    /// Detection reads the `TZ` environment variable, then `/etc/timezone` and `/etc/localtime` on Unix. Windows names
+    /// its timezones differently and isn't detected, so unless `TZ` is set the timezone should be given here.
+    pub fn set_timezone(&mut self, timezone: Option<&str>) {
+        block_on(self.inner.set_timezone(timezone));
+    }
+
+    /// Goes back to sending the timezone detected from the host, undoing [`set_timezone`](#method.set_timezone)
+    pub fn reset_timezone(&mut self) {
+        block_on(self.inner.reset_timezone());
+    }
+
+    /// Returns the IANA timezone that will be sent to the roboRIO, if any
+    pub fn timezone(&self) -> Option<String> {
+        block_on(self.inner.timezone())
     }
 
     /// Returns the current mode of the robot
     pub fn mode(&self) -> Mode {
-        *block_on(self.state.send().lock()).mode()
+        block_on(self.inner.mode())
     }
 
     /// Enables outputs on the robot
     pub fn enable(&mut self) {
-        block_on(self.state.send().lock()).enable();
+        block_on(self.inner.enable());
     }
 
     /// Instructs the roboRIO to restart robot code
     pub fn restart_code(&mut self) {
-        block_on(self.state.send().lock()).request(Request::RESTART_CODE);
+        block_on(self.inner.restart_code());
     }
 
     /// Instructs the roboRIO to reboot
     pub fn restart_roborio(&mut self) {
-        block_on(self.state.send().lock()).request(Request::REBOOT_ROBORIO);
+        block_on(self.inner.restart_roborio());
     }
 
     /// Returns whether the robot is currently enabled
     pub fn enabled(&self) -> bool {
-        block_on(self.state.send().lock()).enabled()
+        block_on(self.inner.enabled())
+    }
+
+    /// Returns the mode the robot reports that it is in
+    ///
+    /// This can differ from [`mode`](#method.mode), which is the mode that the driver station is requesting.
+    /// Returns `None` if the robot isn't connected.
+    pub fn robot_mode(&self) -> Option<Mode> {
+        block_on(self.inner.robot_mode())
+    }
+
+    /// Returns whether the robot reports that its outputs are enabled
+    ///
+    /// This can differ from [`enabled`](#method.enabled), which is whether the driver station is requesting that the robot be enabled.
+    pub fn robot_enabled(&self) -> bool {
+        block_on(self.inner.robot_enabled())
+    }
+
+    /// Returns whether the robot reports that it is browning out
+    pub fn robot_browning_out(&self) -> bool {
+        block_on(self.inner.robot_browning_out())
+    }
+
+    /// Returns whether the robot reports that user code is running
+    pub fn robot_code_running(&self) -> bool {
+        block_on(self.inner.robot_code_running())
     }
 
     /// Returns the last received Trace from the robot
     pub fn trace(&self) -> Trace {
-        *block_on(self.state.recv().lock()).trace()
+        block_on(self.inner.trace())
     }
 
     /// Returns the last received battery voltage from the robot
     pub fn battery_voltage(&self) -> f32 {
-        block_on(self.state.recv().lock()).battery_voltage()
+        block_on(self.inner.battery_voltage())
+    }
+
+    /// Returns the most recent power distribution telemetry reported by the robot, from either a CTRE PDP or a REV PDH
+    ///
+    /// Returns `None` if the roboRIO hasn't sent any since the connection was established
+    pub fn pdp_log(&self) -> Option<PdpLog> {
+        block_on(self.inner.pdp_log())
+    }
+
+    /// Returns the most recent per-core CPU usage reported by the roboRIO
+    pub fn cpu_info(&self) -> Option<CpuInfo> {
+        block_on(self.inner.cpu_info())
+    }
+
+    /// Returns the most recent memory usage reported by the roboRIO
+    pub fn ram_info(&self) -> Option<RamInfo> {
+        block_on(self.inner.ram_info())
+    }
+
+    /// Returns the most recent free disk space reported by the roboRIO
+    pub fn disk_info(&self) -> Option<DiskInfo> {
+        block_on(self.inner.disk_info())
+    }
+
+    /// Returns the most recent CAN bus metrics reported by the roboRIO
+    pub fn can_metrics(&self) -> Option<CanMetrics> {
+        block_on(self.inner.can_metrics())
+    }
+
+    /// Returns the most recent HID outputs and rumble values set by robot code, indexed by joystick port
+    ///
+    /// Robot code sets these through methods such as `GenericHID.setRumble` and `GenericHID.setOutput`.
+    pub fn joystick_outputs(&self) -> Vec<JoystickOutput> {
+        block_on(self.inner.joystick_outputs())
+    }
+
+    /// Returns a snapshot of the round trip time and packet loss of the connection to the robot
+    pub fn connection_stats(&self) -> ConnectionStats {
+        block_on(self.inner.connection_stats())
+    }
+
+    /// Returns the most recent contents of each UDP tag sent by the roboRIO that this crate doesn't decode, ordered by id
+    ///
+    /// Tags with a known id that fail to decode, such as a power distribution log with an unrecognised layout, are included.
+    ///
+    /// TCP packets that aren't decoded are passed to the TCP consumer as [`TcpPacket::Unknown`](enum.TcpPacket.html#variant.Unknown).
+    pub fn unknown_udp_tags(&self) -> Vec<UnknownTag> {
+        block_on(self.inner.unknown_udp_tags())
+    }
+
+    /// Returns the state of the TCP connection to the roboRIO, which carries robot code output and versions
+    ///
+    /// Lost connections and failed attempts to connect are retried with a backoff, until the target of the driver station changes.
+    pub fn tcp_connection_state(&self) -> TcpConnectionState {
+        block_on(self.inner.tcp_connection_state())
+    }
+
+    /// Returns the error that stopped the connection to the roboRIO, if it has stopped
+    ///
+    /// Once stopped, packets are no longer exchanged with the roboRIO and the driver station should be recreated.
+    pub fn udp_error(&self) -> Option<String> {
+        block_on(self.inner.udp_error())
+    }
+
+    /// Returns the versions of the roboRIO image, WPILib, vendor libraries and devices reported by the robot
+    ///
+    /// The roboRIO reports these when robot code starts, they are forgotten when the TCP connection is reestablished.
+    pub fn versions(&self) -> Vec<VersionInfo> {
+        block_on(self.inner.versions())
+    }
+
+    /// Returns the number of times the robot has been disabled due to comms loss or 12V brownouts
+    ///
+    /// The counts are totals since this driver station was created, and include faults from before the roboRIO last rebooted.
+    pub fn disable_faults(&self) -> DisableFaults {
+        block_on(self.inner.disable_faults())
+    }
+
+    /// Returns the number of faults on the roboRIO's 6V, 5V and 3.3V power rails
+    ///
+    /// The counts are totals since this driver station was created, and include faults from before the roboRIO last rebooted.
+    pub fn rail_faults(&self) -> RailFaults {
+        block_on(self.inner.rail_faults())
     }
 
     /// Queues a UDP tag to be transmitted with the next outbound packet to the roboRIO
+    ///
+    /// Tags with more than 254 bytes of data don't fit in a packet, and are dropped when it's encoded.
     pub fn queue_udp(&mut self, udp_tag: UdpTag) {
-        block_on(self.state.send().lock()).queue_udp(udp_tag);
+        block_on(self.inner.queue_udp(udp_tag));
     }
 
     /// Returns a Vec of the current contents of the UDP queue
     pub fn udp_queue(&self) -> Vec<UdpTag> {
-        block_on(self.state.send().lock()).pending_udp().clone()
+        block_on(self.inner.udp_queue())
     }
 
     /// Queues a TCP tag to be transmitted to the roboRIO
-    pub fn queue_tcp(&mut self, tcp_tag: TcpTag) {
-        let _ = block_on(self.state.tcp().lock()).queue_tcp(tcp_tag);
+    ///
+    /// Game data, match info and joystick descriptors are sent again whenever the roboRIO reconnects.
+    /// Other tags queued while the roboRIO is disconnected are buffered until it connects, up to a limit of 64.
+    ///
+    /// Fails if the tag is too large to be sent in a single frame.
+    pub fn queue_tcp(&mut self, tcp_tag: TcpTag) -> Result<()> {
+        block_on(self.inner.queue_tcp(tcp_tag))
+    }
+
+    /// Returns a Vec of the TCP tags that are waiting for the roboRIO to connect
+    pub fn tcp_queue(&self) -> Vec<TcpTag> {
+        block_on(self.inner.tcp_queue())
     }
 
     /// Disables outputs on the robot and disallows enabling it until the code is restarted.
     pub fn estop(&mut self) {
-        block_on(self.state.send().lock()).estop();
+        block_on(self.inner.estop());
     }
 
     /// Returns whether the robot is currently E-stopped
     pub fn estopped(&self) -> bool {
-        block_on(self.state.send().lock()).estopped()
+        block_on(self.inner.estopped())
     }
 
     /// Disables outputs on the robot
     pub fn disable(&mut self) {
-        block_on(self.state.send().lock()).disable();
+        block_on(self.inner.disable());
     }
 }
 
@@ -205,11 +346,15 @@ impl DriverStation {
 pub enum JoystickValue {
     /// Represents an axis value to be sent to the roboRIO
     ///
-    /// `value` should range from `-1.0..=1.0`, or `0.0..=1.0` if the axis is a trigger
+    /// `id` ranges from `0..=11`. `value` should range from `-1.0..=1.0`, or `0.0..=1.0` if the axis is a trigger
     Axis { id: u8, value: f32 },
     /// Represents a button value to be sent to the roboRIO
+    ///
+    /// `id` ranges from `1..=32`
     Button { id: u8, pressed: bool },
     /// Represents a POV, or D-pad value to be sent to the roboRIO
+    ///
+    /// `id` ranges from `0..=3`. `angle` is in degrees, or -1 if the POV isn't pressed
     POV { id: u8, angle: i16 },
 }
 
@@ -223,31 +368,15 @@ impl JoystickValue {
     }
 
     pub fn is_axis(self) -> bool {
-        match self {
-            JoystickValue::Axis { .. } => true,
-            _ => false,
-        }
+        matches!(self, JoystickValue::Axis { .. })
     }
 
     pub fn is_button(self) -> bool {
-        match self {
-            JoystickValue::Button { .. } => true,
-            _ => false,
-        }
+        matches!(self, JoystickValue::Button { .. })
     }
 
     pub fn is_pov(self) -> bool {
-        match self {
-            JoystickValue::POV { .. } => true,
-            _ => false,
-        }
-    }
-}
-
-impl Drop for DriverStation {
-    fn drop(&mut self) {
-        // When this struct is dropped the threads that we spawned should be stopped otherwise we're leaking
-        let _ = self.thread_tx.unbounded_send(Signal::Disconnect);
+        matches!(self, JoystickValue::POV { .. })
     }
 }
 
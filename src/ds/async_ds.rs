@@ -0,0 +1,601 @@
+use failure::bail;
+
+use super::builder::Config;
+use super::conn::*;
+use super::router;
+use super::state::*;
+use super::{DriverStation, JoystickValue, Signal};
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use futures_channel::mpsc::{unbounded, UnboundedSender};
+use tokio::net::UdpSocket;
+use tokio::runtime::Handle;
+
+use crate::proto::tcp::outbound::{GameData, JoystickDescriptor, TcpTag};
+use crate::proto::udp::inbound::types::tags::{
+    CanMetrics, CpuInfo, DiskInfo, JoystickOutput, PdpLog, RamInfo, UnknownTag,
+};
+use crate::proto::udp::inbound::types::Trace;
+use crate::proto::udp::outbound::types::tags::UdpTag;
+use crate::proto::udp::outbound::types::*;
+use crate::util::ip_from_team_number;
+use crate::{DisableFaults, RailFaults, Result, TcpConnectionState, TcpPacket, VersionInfo};
+
+/// Represents a connection to the roboRIO acting as a driver station, for use from async code
+///
+/// The network connections run as tasks on an existing tokio runtime, rather than on a runtime of their own.
+/// Methods that access the state of the driver station are `async`, and never block the thread that they're called from.
+pub struct AsyncDriverStation {
+    thread_tx: UnboundedSender<Signal>,
+    team_number: AtomicU32,
+    /// Keeps the simulator port open, the task detecting the simulator stops once this is dropped
+    _sim: Option<router::Subscription>,
+    state: Arc<DsState>,
+}
+
+impl AsyncDriverStation {
+    /// Creates a new driver station with the given team number and alliance, running on the current tokio runtime
+    ///
+    /// This driver station will attempt to connect to a roboRIO at 10.TE.AM.2,
+    /// if the roboRIO is at a different ip, use [`new`](#method.new) and specify the ip directly.
+    /// Fails if the sockets used to communicate with the roboRIO can't be bound.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside of a tokio runtime.
+    pub fn new_team(team_number: u32, alliance: Alliance) -> Result<AsyncDriverStation> {
+        Self::new(&ip_from_team_number(team_number), alliance, team_number)
+    }
+
+    /// Creates a new driver station for the given alliance station and team number, running on the current tokio runtime
+    /// Connects to the roborio at `ip`. To infer the ip from team_number, use `new_team` instead.
+    /// Fails if the sockets used to communicate with the roboRIO can't be bound.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside of a tokio runtime.
+    pub fn new(ip: &str, alliance: Alliance, team_number: u32) -> Result<AsyncDriverStation> {
+        Self::with_handle(ip, alliance, team_number, &Handle::current())
+    }
+
+    /// Creates a new driver station for the given alliance station and team number, running on the runtime of `handle`
+    /// Connects to the roborio at `ip`. Fails if the sockets used to communicate with the roboRIO can't be bound.
+    pub fn with_handle(
+        ip: &str,
+        alliance: Alliance,
+        team_number: u32,
+        handle: &Handle,
+    ) -> Result<AsyncDriverStation> {
+        DriverStation::builder(team_number, alliance)
+            .target_ip(ip)
+            .build_async_with_handle(handle)
+    }
+
+    pub(crate) fn with_config(
+        ip: &str,
+        alliance: Alliance,
+        team_number: u32,
+        config: Config,
+        handle: &Handle,
+    ) -> Result<AsyncDriverStation> {
+        // The sockets are bound here, so that failing to bind them is reported to the caller instead of ending a task.
+        // The receiving port is shared with other driver stations in the process, packets are routed by the roboRIO that sent them
+        let local = SocketAddr::new(config.bind_address, config.udp_recv_port);
+        let (route, udp_rx) = router::subscribe(local, Vec::new(), handle)?;
+        // The simulator announces itself from the loopback address, to every driver station that detects it
+        let sim = if config.detects_simulator() {
+            let local = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), config.sim_port);
+            Some(router::subscribe(
+                local,
+                vec![Ipv4Addr::LOCALHOST.into()],
+                handle,
+            )?)
+        } else {
+            None
+        };
+        let udp_tx = std::net::UdpSocket::bind((config.bind_address, 0))?;
+        let udp_tx = handle.enter(|| UdpSocket::from_std(udp_tx))?;
+
+        // Channels to communicate to the tasks that make up the application, used to break out of infinite loops when the struct is dropped
+        let (tx, rx) = unbounded::<Signal>();
+
+        // Global state of the driver station
+        let state = Arc::new(DsState::new(alliance));
+
+        // Tasks containing UDP sockets communicating with the roboRIO
+        let udp_state = state.clone();
+        let udp_ip = ip.to_owned();
+
+        let sim = sim.map(|(subscription, sim_rx)| {
+            handle.spawn(sim_conn(tx.clone(), sim_rx));
+            subscription
+        });
+        handle.spawn(async move {
+            let sockets = UdpSockets {
+                route,
+                rx: udp_rx,
+                tx: udp_tx,
+            };
+            let error_state = udp_state.clone();
+            if let Err(e) = udp_conn(udp_state, udp_ip, rx, sockets, config).await {
+                error_state.recv().lock().await.set_udp_error(e.to_string());
+            }
+        });
+
+        Ok(AsyncDriverStation {
+            thread_tx: tx,
+            state,
+            team_number: AtomicU32::new(team_number),
+            _sim: sim,
+        })
+    }
+
+    /// Provides a closure that will be called when constructing outbound packets to append joystick values
+    ///
+    /// Each joystick is sent with the axis, button and POV counts from the descriptor for its port, or 6 axes, 10 buttons
+    /// and 1 POV before a descriptor is sent. Values past those counts grow them for all later packets.
+    pub async fn set_joystick_supplier(
+        &self,
+        supplier: impl Fn() -> Vec<Vec<JoystickValue>> + Send + Sync + 'static,
+    ) {
+        self.state
+            .send()
+            .lock()
+            .await
+            .set_joystick_supplier(supplier);
+    }
+
+    /// Provides a closure that will be called to describe the joysticks plugged into each port
+    ///
+    /// The descriptors are sent to the roboRIO when it connects and whenever they change, allowing robot code to
+    /// query joystick names and types. The `index` of each descriptor is set from its position. Ports that the joystick
+    /// supplier provides values for but the closure doesn't describe are sent a default descriptor, without a name and
+    /// with the counts that the port is sent with.
+    pub async fn set_joystick_descriptor_supplier(
+        &self,
+        supplier: impl Fn() -> Vec<JoystickDescriptor> + Send + Sync + 'static,
+    ) {
+        self.state
+            .tcp()
+            .lock()
+            .await
+            .set_joystick_descriptor_supplier(supplier);
+    }
+
+    /// Provides a closure that will be called when TCP packets are received from the roboRIO
+    ///
+    /// Example usage: Logging all stdout messages from robot code.
+    pub async fn set_tcp_consumer(&self, consumer: impl FnMut(TcpPacket) + Send + Sync + 'static) {
+        self.state.tcp().lock().await.set_tcp_consumer(consumer);
+    }
+
+    /// Changes the alliance for the given `DriverStation`
+    pub async fn set_alliance(&self, alliance: Alliance) {
+        self.state.send().lock().await.set_alliance(alliance);
+    }
+
+    /// Changes the given `mode` the robot will be in
+    pub async fn set_mode(&self, mode: Mode) {
+        self.state.send().lock().await.set_mode(mode);
+    }
+
+    pub async fn ds_mode(&self) -> DsMode {
+        *self.state.send().lock().await.ds_mode()
+    }
+
+    /// Changes the team number of this driver station, as well as the ip the driver station will attempt to connect to.
+    /// The ip of the new roboRIO target is 10.TE.AM.2
+    pub fn set_team_number(&self, team_number: u32) {
+        self.team_number.store(team_number, Ordering::Relaxed);
+        // The tasks only stop when this is dropped, or after an error that they've already recorded
+        let _ = self
+            .thread_tx
+            .unbounded_send(Signal::NewTarget(ip_from_team_number(team_number)));
+    }
+
+    pub fn set_use_usb(&self, use_usb: bool) {
+        let target = if use_usb {
+            "172.22.11.2".to_string()
+        } else {
+            ip_from_team_number(self.team_number())
+        };
+        let _ = self.thread_tx.unbounded_send(Signal::NewTarget(target));
+    }
+
+    pub fn team_number(&self) -> u32 {
+        self.team_number.load(Ordering::Relaxed)
+    }
+
+    /// Sets the game specific message sent to the robot, and used during the autonomous period
+    ///
+    /// The format of the message changes between seasons, it can be up to 255 bytes long.
+    pub async fn set_game_specific_message(&self, message: &str) -> Result<()> {
+        if message.len() > 255 {
+            bail!("Message should be at most 255 bytes long");
+        }
+
+        self.state
+            .tcp()
+            .lock()
+            .await
+            .queue_tcp(TcpTag::GameData(GameData {
+                gsm: message.to_string(),
+            }));
+        Ok(())
+    }
+
+    /// Sets the IANA timezone, such as `America/Toronto`, that the roboRIO is set to alongside its clock
+    ///
+    /// By default the timezone is detected from the host. If it can't be detected, or `None` is given, only the time is
+    /// sent, in UTC.
+    ///
+    /// Detection reads the `TZ` environment variable, then `/etc/timezone` and `/etc/localtime` on Unix. Windows names
+    /// its timezones differently and isn't detected, so unless `TZ` is set the timezone should be given here.
+    pub async fn set_timezone(&self, timezone: Option<&str>) {
+        self.state.send().lock().await.set_timezone(timezone);
+    }
+
+    /// Goes back to sending the timezone detected from the host, undoing [`set_timezone`](#method.set_timezone)
+    pub async fn reset_timezone(&self) {
+        self.state.send().lock().await.reset_timezone();
+    }
+
+    /// Returns the IANA timezone that will be sent to the roboRIO, if any
+    pub async fn timezone(&self) -> Option<String> {
+        self.state
+            .send()
+            .lock()
+            .await
+            .timezone()
+            .map(str::to_string)
+    }
+
+    /// Returns the current mode of the robot
+    pub async fn mode(&self) -> Mode {
+        *self.state.send().lock().await.mode()
+    }
+
+    /// Enables outputs on the robot
+    pub async fn enable(&self) {
+        self.state.send().lock().await.enable();
+    }
+
+    /// Instructs the roboRIO to restart robot code
+    pub async fn restart_code(&self) {
+        self.state
+            .send()
+            .lock()
+            .await
+            .request(Request::RESTART_CODE);
+    }
+
+    /// Instructs the roboRIO to reboot
+    pub async fn restart_roborio(&self) {
+        self.state
+            .send()
+            .lock()
+            .await
+            .request(Request::REBOOT_ROBORIO);
+    }
+
+    /// Returns whether the robot is currently enabled
+    pub async fn enabled(&self) -> bool {
+        self.state.send().lock().await.enabled()
+    }
+
+    /// Returns the mode the robot reports that it is in
+    ///
+    /// This can differ from [`mode`](#method.mode), which is the mode that the driver station is requesting.
+    /// Returns `None` if the robot isn't connected.
+    pub async fn robot_mode(&self) -> Option<Mode> {
+        self.state
+            .recv()
+            .lock()
+            .await
+            .status()
+            .and_then(Mode::from_status)
+    }
+
+    /// Returns whether the robot reports that its outputs are enabled
+    ///
+    /// This can differ from [`enabled`](#method.enabled), which is whether the driver station is requesting that the robot be enabled.
+    pub async fn robot_enabled(&self) -> bool {
+        self.state
+            .recv()
+            .lock()
+            .await
+            .status()
+            .is_some_and(|status| status.enabled())
+    }
+
+    /// Returns whether the robot reports that it is browning out
+    pub async fn robot_browning_out(&self) -> bool {
+        self.state
+            .recv()
+            .lock()
+            .await
+            .status()
+            .is_some_and(|status| status.is_browning_out())
+    }
+
+    /// Returns whether the robot reports that user code is running
+    pub async fn robot_code_running(&self) -> bool {
+        self.state.recv().lock().await.trace().is_code_started()
+    }
+
+    /// Returns the last received Trace from the robot
+    pub async fn trace(&self) -> Trace {
+        *self.state.recv().lock().await.trace()
+    }
+
+    /// Returns the last received battery voltage from the robot
+    pub async fn battery_voltage(&self) -> f32 {
+        self.state.recv().lock().await.battery_voltage()
+    }
+
+    /// Returns the most recent power distribution telemetry reported by the robot, from either a CTRE PDP or a REV PDH
+    ///
+    /// Returns `None` if the roboRIO hasn't sent any since the connection was established
+    pub async fn pdp_log(&self) -> Option<PdpLog> {
+        self.state.recv().lock().await.pdp_log().cloned()
+    }
+
+    /// Returns the most recent per-core CPU usage reported by the roboRIO
+    pub async fn cpu_info(&self) -> Option<CpuInfo> {
+        self.state.recv().lock().await.cpu_info().cloned()
+    }
+
+    /// Returns the most recent memory usage reported by the roboRIO
+    pub async fn ram_info(&self) -> Option<RamInfo> {
+        self.state.recv().lock().await.ram_info().copied()
+    }
+
+    /// Returns the most recent free disk space reported by the roboRIO
+    pub async fn disk_info(&self) -> Option<DiskInfo> {
+        self.state.recv().lock().await.disk_info().copied()
+    }
+
+    /// Returns the most recent CAN bus metrics reported by the roboRIO
+    pub async fn can_metrics(&self) -> Option<CanMetrics> {
+        self.state.recv().lock().await.can_metrics().copied()
+    }
+
+    /// Returns the most recent HID outputs and rumble values set by robot code, indexed by joystick port
+    ///
+    /// Robot code sets these through methods such as `GenericHID.setRumble` and `GenericHID.setOutput`.
+    pub async fn joystick_outputs(&self) -> Vec<JoystickOutput> {
+        self.state.recv().lock().await.joystick_outputs().clone()
+    }
+
+    /// Returns a snapshot of the round trip time and packet loss of the connection to the robot
+    pub async fn connection_stats(&self) -> ConnectionStats {
+        self.state.stats().lock().await.stats()
+    }
+
+    /// Returns the most recent contents of each UDP tag sent by the roboRIO that this crate doesn't decode, ordered by id
+    ///
+    /// Tags with a known id that fail to decode, such as a power distribution log with an unrecognised layout, are included.
+    ///
+    /// TCP packets that aren't decoded are passed to the TCP consumer as [`TcpPacket::Unknown`](enum.TcpPacket.html#variant.Unknown).
+    pub async fn unknown_udp_tags(&self) -> Vec<UnknownTag> {
+        self.state.recv().lock().await.unknown_tags().clone()
+    }
+
+    /// Returns the state of the TCP connection to the roboRIO, which carries robot code output and versions
+    ///
+    /// Lost connections and failed attempts to connect are retried with a backoff, until the target of the driver station changes.
+    pub async fn tcp_connection_state(&self) -> TcpConnectionState {
+        self.state.tcp().lock().await.connection_state().clone()
+    }
+
+    /// Returns the error that stopped the connection to the roboRIO, if it has stopped
+    ///
+    /// Once stopped, packets are no longer exchanged with the roboRIO and the driver station should be recreated.
+    pub async fn udp_error(&self) -> Option<String> {
+        self.state
+            .recv()
+            .lock()
+            .await
+            .udp_error()
+            .map(str::to_string)
+    }
+
+    /// Returns the versions of the roboRIO image, WPILib, vendor libraries and devices reported by the robot
+    ///
+    /// The roboRIO reports these when robot code starts, they are forgotten when the TCP connection is reestablished.
+    pub async fn versions(&self) -> Vec<VersionInfo> {
+        self.state.tcp().lock().await.versions().clone()
+    }
+
+    /// Returns the number of times the robot has been disabled due to comms loss or 12V brownouts
+    ///
+    /// The counts are totals since this driver station was created, and include faults from before the roboRIO last rebooted.
+    pub async fn disable_faults(&self) -> DisableFaults {
+        self.state.tcp().lock().await.disable_faults()
+    }
+
+    /// Returns the number of faults on the roboRIO's 6V, 5V and 3.3V power rails
+    ///
+    /// The counts are totals since this driver station was created, and include faults from before the roboRIO last rebooted.
+    pub async fn rail_faults(&self) -> RailFaults {
+        self.state.tcp().lock().await.rail_faults()
+    }
+
+    /// Queues a UDP tag to be transmitted with the next outbound packet to the roboRIO
+    ///
+    /// Tags with more than 254 bytes of data don't fit in a packet, and are dropped when it's encoded.
+    pub async fn queue_udp(&self, udp_tag: UdpTag) {
+        self.state.send().lock().await.queue_udp(udp_tag);
+    }
+
+    /// Returns a Vec of the current contents of the UDP queue
+    pub async fn udp_queue(&self) -> Vec<UdpTag> {
+        self.state.send().lock().await.pending_udp().clone()
+    }
+
+    /// Queues a TCP tag to be transmitted to the roboRIO
+    ///
+    /// Game data, match info and joystick descriptors are sent again whenever the roboRIO reconnects.
+    /// Other tags queued while the roboRIO is disconnected are buffered until it connects, up to a limit of 64.
+    ///
+    /// Fails if the tag is too large to be sent in a single frame.
+    pub async fn queue_tcp(&self, tcp_tag: TcpTag) -> Result<()> {
+        // Tags are checked here, so that one that can't be sent doesn't take down the connection that sends it
+        tcp_tag.construct()?;
+        self.state.tcp().lock().await.queue_tcp(tcp_tag);
+        Ok(())
+    }
+
+    /// Returns a Vec of the TCP tags that are waiting for the roboRIO to connect
+    pub async fn tcp_queue(&self) -> Vec<TcpTag> {
+        self.state
+            .tcp()
+            .lock()
+            .await
+            .buffered_tcp()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Disables outputs on the robot and disallows enabling it until the code is restarted.
+    pub async fn estop(&self) {
+        self.state.send().lock().await.estop();
+    }
+
+    /// Returns whether the robot is currently E-stopped
+    pub async fn estopped(&self) -> bool {
+        self.state.send().lock().await.estopped()
+    }
+
+    /// Disables outputs on the robot
+    pub async fn disable(&self) {
+        self.state.send().lock().await.disable();
+    }
+}
+
+impl Drop for AsyncDriverStation {
+    fn drop(&mut self) {
+        // When this struct is dropped the tasks that we spawned should be stopped otherwise we're leaking
+        let _ = self.thread_tx.unbounded_send(Signal::Disconnect);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+    use tokio::runtime::Builder;
+    use tokio::time;
+
+    /// Returns a port on the loopback address that nothing is bound to
+    fn free_port() -> u16 {
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket.local_addr().unwrap().port()
+    }
+
+    fn builder(robot_port: u16) -> crate::DriverStationBuilder {
+        DriverStation::builder(1234, Alliance::new_red(1))
+            .target_ip("127.0.0.1")
+            .udp_send_port(robot_port)
+            .udp_recv_port(0)
+            .sim_port(free_port())
+            .bind_address(Ipv4Addr::LOCALHOST.into())
+    }
+
+    #[test]
+    fn construct_and_drop() {
+        let mut rt = Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let ds = builder(free_port()).build_async().unwrap();
+            assert_eq!(ds.team_number(), 1234);
+            assert_eq!(ds.udp_error().await, None);
+
+            // Changing the team number doesn't need a mutable driver station, like the other setters
+            ds.set_team_number(4321);
+            assert_eq!(ds.team_number(), 4321);
+        });
+
+        // The tasks are dropped along with the runtime
+        drop(rt);
+    }
+
+    #[test]
+    fn runs_on_callers_runtime() {
+        // Without worker threads, the driver station can only make progress while this runtime is polled
+        let mut rt = Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let mut robot = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+            let ds = builder(robot.local_addr().unwrap().port())
+                .build_async()
+                .unwrap();
+
+            let mut buf = [0; 1024];
+            let recv = time::timeout(Duration::from_secs(2), robot.recv_from(&mut buf));
+            let (len, _) = recv.await.expect("No control packet was sent").unwrap();
+            assert!(len >= 6);
+            // The communication version of the control packet
+            assert_eq!(buf[2], 0x01);
+
+            // Dropping the driver station stops it sending, once its tasks notice
+            drop(ds);
+            time::delay_for(Duration::from_millis(100)).await;
+            let mut stopped = false;
+            for _ in 0..20 {
+                let recv = time::timeout(Duration::from_millis(100), robot.recv_from(&mut buf));
+                if recv.await.is_err() {
+                    stopped = true;
+                    break;
+                }
+            }
+            assert!(stopped);
+        });
+    }
+
+    #[test]
+    fn share_simulator_port() {
+        let mut rt = Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let sim_port = free_port();
+            let first = builder(free_port())
+                .sim_port(sim_port)
+                .build_async()
+                .unwrap();
+            let second = builder(free_port())
+                .sim_port(sim_port)
+                .build_async()
+                .unwrap();
+
+            // Every driver station switches to simulation mode when the simulator announces itself
+            let mut sim = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+            for _ in 0..100 {
+                sim.send_to(&[0], (Ipv4Addr::LOCALHOST, sim_port))
+                    .await
+                    .unwrap();
+                time::delay_for(Duration::from_millis(10)).await;
+                if first.ds_mode().await == DsMode::Simulation
+                    && second.ds_mode().await == DsMode::Simulation
+                {
+                    return;
+                }
+            }
+            panic!("Both driver stations should have detected the simulator");
+        });
+    }
+}
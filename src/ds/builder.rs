@@ -0,0 +1,274 @@
+use super::{AsyncDriverStation, DriverStation};
+use crate::proto::udp::outbound::types::Alliance;
+use crate::util::ip_from_team_number;
+use crate::Result;
+use futures_channel::oneshot;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::{Handle, Runtime};
+
+/// The ports, timings and interface used by the network connections of a driver station
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Config {
+    /// The port on the roboRIO that control packets are sent to
+    pub udp_send_port: u16,
+    /// The local port that status packets from the roboRIO are received on
+    pub udp_recv_port: u16,
+    /// The port on the roboRIO that the TCP connection is made to
+    pub tcp_port: u16,
+    /// The local port that the simulator is detected on
+    pub sim_port: u16,
+    /// How long to wait for a status packet before considering the roboRIO disconnected
+    pub recv_timeout: Duration,
+    /// How often control packets are sent to the roboRIO
+    pub send_interval: Duration,
+    /// The longest that the exponential backoff waits between retries
+    pub max_backoff: Duration,
+    /// The local address that sockets are bound to
+    pub bind_address: IpAddr,
+}
+
+impl Config {
+    /// Whether the status packets that the simulator sends to the loopback address can be received
+    pub fn detects_simulator(&self) -> bool {
+        self.bind_address.is_unspecified() || self.bind_address.is_loopback()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            udp_send_port: 1110,
+            udp_recv_port: 1150,
+            tcp_port: 1740,
+            sim_port: 1135,
+            recv_timeout: Duration::from_secs(2),
+            send_interval: Duration::from_millis(20),
+            max_backoff: Duration::from_secs(5),
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        }
+    }
+}
+
+/// Builder for a [`DriverStation`](struct.DriverStation.html) or [`AsyncDriverStation`](struct.AsyncDriverStation.html)
+/// that doesn't use the default ports and timings of the NI driver station
+///
+/// Construct one with [`DriverStation::builder`](struct.DriverStation.html#method.builder).
+#[derive(Debug, Clone)]
+pub struct DriverStationBuilder {
+    team_number: u32,
+    alliance: Alliance,
+    target_ip: String,
+    config: Config,
+}
+
+impl DriverStationBuilder {
+    pub(crate) fn new(team_number: u32, alliance: Alliance) -> DriverStationBuilder {
+        DriverStationBuilder {
+            team_number,
+            alliance,
+            target_ip: ip_from_team_number(team_number),
+            config: Config::default(),
+        }
+    }
+
+    /// Sets the ip of the roboRIO to connect to, instead of 10.TE.AM.2
+    pub fn target_ip(mut self, ip: &str) -> Self {
+        self.target_ip = ip.to_string();
+        self
+    }
+
+    /// Sets the port on the roboRIO that control packets are sent to. Defaults to 1110
+    pub fn udp_send_port(mut self, port: u16) -> Self {
+        self.config.udp_send_port = port;
+        self
+    }
+
+    /// Sets the local port that status packets from the roboRIO are received on. Defaults to 1150
+    ///
+    /// The port is shared by every driver station in the process with the same [`bind_address`](#method.bind_address),
+    /// and by those bound to a single interface once one bound to the unspecified address is using it. Binding the
+    /// unspecified address fails while a driver station bound to a single interface is using the port.
+    pub fn udp_recv_port(mut self, port: u16) -> Self {
+        self.config.udp_recv_port = port;
+        self
+    }
+
+    /// Sets the port on the roboRIO that the TCP connection is made to. Defaults to 1740
+    pub fn tcp_port(mut self, port: u16) -> Self {
+        self.config.tcp_port = port;
+        self
+    }
+
+    /// Sets the local port that robot simulators announce themselves on. Defaults to 1135
+    ///
+    /// The port is shared by every driver station in the process, building fails if another process is using it.
+    /// Unused if [`bind_address`](#method.bind_address) disables simulator detection.
+    pub fn sim_port(mut self, port: u16) -> Self {
+        self.config.sim_port = port;
+        self
+    }
+
+    /// Sets how long to wait for a status packet before the roboRIO is considered disconnected. Defaults to 2 seconds
+    pub fn recv_timeout(mut self, timeout: Duration) -> Self {
+        self.config.recv_timeout = timeout;
+        self
+    }
+
+    /// Sets how often control packets are sent to the roboRIO. Defaults to 20ms
+    pub fn send_interval(mut self, interval: Duration) -> Self {
+        self.config.send_interval = interval;
+        self
+    }
+
+    /// Sets the longest time to wait between retries when sending or connecting fails. Defaults to 5 seconds
+    pub fn max_backoff(mut self, max: Duration) -> Self {
+        self.config.max_backoff = max;
+        self
+    }
+
+    /// Sets the local address that sockets are bound to, to pin the connection to the roboRIO to one interface
+    ///
+    /// Defaults to the unspecified address, which lets the operating system pick the interface.
+    ///
+    /// The simulator sends status packets to the loopback address, which a socket bound to another interface doesn't
+    /// receive. Simulator detection is disabled unless this is the unspecified address or a loopback address.
+    pub fn bind_address(mut self, address: IpAddr) -> Self {
+        self.config.bind_address = address;
+        self
+    }
+
+    /// Creates a driver station that runs on a tokio runtime of its own
+    ///
+    /// Fails if the runtime can't be started, or if the sockets used to communicate with the roboRIO can't be bound.
+    pub fn build(self) -> Result<DriverStation> {
+        // The driver station gets its own runtime, so that it can be used from synchronous code.
+        // The runtime lives on its own thread so that it can be shut down when the driver station is dropped from async code.
+        let (handle_tx, handle_rx) = mpsc::channel();
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        thread::spawn(move || match Runtime::new() {
+            Ok(mut rt) => {
+                let _ = handle_tx.send(Ok(rt.handle().clone()));
+                let _ = rt.block_on(stop_rx);
+            }
+            Err(e) => {
+                let _ = handle_tx.send(Err(e));
+            }
+        });
+        let handle = handle_rx.recv()??;
+
+        Ok(DriverStation {
+            inner: self.build_async_with_handle(&handle)?,
+            _runtime_stop: stop_tx,
+        })
+    }
+
+    /// Creates a driver station that runs on the current tokio runtime
+    ///
+    /// Fails if the sockets used to communicate with the roboRIO can't be bound.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside of a tokio runtime.
+    pub fn build_async(self) -> Result<AsyncDriverStation> {
+        self.build_async_with_handle(&Handle::current())
+    }
+
+    /// Creates a driver station that runs on the runtime of `handle`
+    ///
+    /// Fails if the sockets used to communicate with the roboRIO can't be bound.
+    pub fn build_async_with_handle(self, handle: &Handle) -> Result<AsyncDriverStation> {
+        AsyncDriverStation::with_config(
+            &self.target_ip,
+            self.alliance,
+            self.team_number,
+            self.config,
+            handle,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::TcpConnectionState;
+    use std::net::{Ipv6Addr, TcpListener, UdpSocket};
+    use std::time::Instant;
+
+    /// Returns a port on the loopback address that nothing is bound to
+    fn free_port() -> u16 {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket.local_addr().unwrap().port()
+    }
+
+    /// Polls `f` until it returns true, panicking after a few seconds
+    fn wait_for(what: &str, mut f: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !f() {
+            assert!(Instant::now() < deadline, "Timed out waiting for {}", what);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn custom_ports() {
+        let robot = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        robot
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let recv_port = free_port();
+
+        let ds = DriverStation::builder(1234, Alliance::new_red(1))
+            .target_ip("127.0.0.1")
+            .udp_send_port(robot.local_addr().unwrap().port())
+            .udp_recv_port(recv_port)
+            .tcp_port(listener.local_addr().unwrap().port())
+            .sim_port(free_port())
+            .send_interval(Duration::from_millis(5))
+            .bind_address(Ipv4Addr::LOCALHOST.into())
+            .build()
+            .unwrap();
+
+        // Control packets are sent to the custom port
+        let mut buf = [0; 1024];
+        let (_, ds_addr) = robot.recv_from(&mut buf).unwrap();
+        assert_eq!(ds_addr.ip(), Ipv4Addr::LOCALHOST);
+
+        // Status packets are received on the custom port, after which TCP connects to the custom port
+        let mut seqnum = 0u16;
+        let mut conn = None;
+        wait_for("the roboRIO to connect", || {
+            seqnum += 1;
+            let mut status = seqnum.to_be_bytes().to_vec();
+            status.extend_from_slice(&[0x01, 0x04, 0x20, 0x0c, 0x80, 0x00]);
+            robot
+                .send_to(&status, (Ipv4Addr::LOCALHOST, recv_port))
+                .unwrap();
+
+            if conn.is_none() {
+                conn = listener.accept().ok();
+            }
+            ds.battery_voltage() == 12.5
+                && ds.tcp_connection_state() == TcpConnectionState::Connected
+        });
+        assert!(conn.is_some());
+    }
+
+    #[test]
+    fn detects_simulator() {
+        let config = |bind_address| Config {
+            bind_address,
+            ..Config::default()
+        };
+
+        assert!(Config::default().detects_simulator());
+        assert!(config(Ipv6Addr::UNSPECIFIED.into()).detects_simulator());
+        assert!(config(Ipv4Addr::LOCALHOST.into()).detects_simulator());
+        assert!(config(Ipv6Addr::LOCALHOST.into()).detects_simulator());
+        assert!(!config(Ipv4Addr::new(10, 12, 34, 5).into()).detects_simulator());
+    }
+}
@@ -1,6 +1,5 @@
 use super::Signal;
 
-use crate::proto::udp::inbound::UdpResponsePacket;
 use crate::proto::udp::outbound::types::tags::{DateTime as DTTag, *};
 
 use futures_channel::mpsc::UnboundedReceiver;
@@ -8,71 +7,97 @@ use futures_channel::mpsc::{unbounded, UnboundedSender};
 use futures_util::sink::SinkExt;
 use futures_util::stream::StreamExt;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::net::{TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+use tokio::net::{self, TcpStream, UdpSocket};
+use tokio::task::JoinHandle;
 use tokio::time;
 use tokio_util::codec::Decoder;
-use tokio_util::udp::UdpFramed;
 
 use chrono::prelude::*;
 
 use crate::proto::tcp::DsTcpCodec;
 use crate::proto::udp::DsUdpCodec;
-use crate::Result;
+use crate::{Result, TcpPacket};
 
-use crate::ds::state::{DsMode, DsState};
+use crate::ds::builder::Config;
+use crate::ds::router::Subscription;
+use crate::ds::state::{DsMode, DsState, TcpConnectionState};
 use crate::proto::tcp::outbound::TcpTag;
-use futures_util::future::Either;
-use futures_util::stream::select;
+use futures_util::future::{self, Either, FutureExt};
+use futures_util::stream::{self, select};
 
 mod backoff;
 
 use backoff::ExponentialBackoff;
-use std::io::ErrorKind;
+use bytes::BytesMut;
+use failure::format_err;
+use net2::TcpBuilder;
+use std::io::{self, ErrorKind};
+use std::iter;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// The sockets used to communicate with the roboRIO over UDP, bound before the driver station is created
+pub(crate) struct UdpSockets {
+    /// The registration of the driver station with the shared receiving socket
+    pub route: Subscription,
+    /// The packets received from the roboRIO
+    pub rx: UnboundedReceiver<BytesMut>,
+    /// The socket that control packets are sent from
+    pub tx: UdpSocket,
+}
 
 /// The root task of the tokio runtime.
 ///
-/// This task connects to the receiving UDP port, and spawns tasks for UDP sending, and for TCP communications once the connection to the RIO has been established.
+/// This task receives status packets, and spawns tasks for UDP sending, and for TCP communications once the connection to the RIO has been established.
 pub(crate) async fn udp_conn(
     state: Arc<DsState>,
     mut target_ip: String,
     rx: UnboundedReceiver<Signal>,
+    sockets: UdpSockets,
+    config: Config,
 ) -> Result<()> {
-    let mut tcp_connected = false;
-    let mut tcp_tx = None;
+    let mut tcp_task = None;
 
-    let udp_rx = UdpSocket::bind("0.0.0.0:1150").await?;
-    let udp_rx = UdpFramed::new(udp_rx, DsUdpCodec);
+    let UdpSockets {
+        route,
+        rx: udp_rx,
+        tx: mut udp_tx,
+    } = sockets;
+    route_to(&route, &target_ip).await;
+    let udp_rx = udp_rx.map(|mut buf| DsUdpCodec.decode(&mut buf));
 
     let (fwd_tx, fwd_rx) = unbounded::<Signal>();
 
     let send_state = state.clone();
     let target = target_ip.clone();
     tokio::spawn(async move {
-        let mut udp_tx = UdpSocket::bind("0.0.0.0:0")
-            .await
-            .expect("Failed to bind tx socket");
-        udp_tx
-            .connect(&format!("{}:1110", target))
-            .await
-            .expect("Failed to connect to target");
+        connect_udp(&mut udp_tx, &target, config.udp_send_port).await;
 
-        let interval = time::interval(Duration::from_millis(20));
+        let interval = time::interval(config.send_interval);
 
-        let mut stream = select(interval.map(Either::Left), fwd_rx.map(Either::Right));
-        let mut backoff = ExponentialBackoff::new(Duration::new(5, 0));
+        // The end of the signals is marked with None, so that the task stops once udp_conn returns
+        let signals = fwd_rx.map(Some).chain(stream::once(future::ready(None)));
+        let mut stream = select(interval.map(Either::Left), signals.map(Either::Right));
+        let mut backoff = ExponentialBackoff::new(config.max_backoff);
+        // Reused between packets so that encoding doesn't allocate
+        let mut buf = BytesMut::with_capacity(1024);
 
         loop {
             let item = stream.next().await.unwrap();
             match item {
                 Either::Left(_) => {
                     let mut state = send_state.send().lock().await;
-                    let v = state.control().encode();
+                    buf.clear();
+                    state.encode_control(&mut buf);
                     // Massively overengineered considering the _only_ time that this actually starts
                     // to come into play is directly after the simulator is closed before the DS switches to Normal mode again
                     // but I don't feel like changing it, and now it's fail safe
-                    match backoff.run(udp_tx.send(&v[..])).await {
-                        Ok(_) => {}
+                    match backoff.run(udp_tx.send(&buf[..])).await {
+                        Ok(_) => send_state
+                            .stats()
+                            .lock()
+                            .await
+                            .sent(state.seqnum(), Instant::now()),
                         Err((e, dc)) => {
                             if e.kind() == ErrorKind::ConnectionRefused && dc {
                                 println!("Send socket disconnected");
@@ -82,33 +107,25 @@ pub(crate) async fn udp_conn(
                     }
                     state.increment_seqnum();
                 }
-                Either::Right(sig) => match sig {
+                Either::Right(None) => break,
+                Either::Right(Some(sig)) => match sig {
                     Signal::NewTarget(ip) => {
                         let mut state = send_state.send().lock().await;
                         state.reset_seqnum();
                         state.disable();
                         send_state.recv().lock().await.reset();
-                        udp_tx = UdpSocket::bind("0.0.0.0:0")
-                            .await
-                            .expect("Failed to bind tx socket");
-                        udp_tx
-                            .connect(&format!("{}:1110", &ip))
-                            .await
-                            .expect("Failed to connect to new target");
+                        send_state.stats().lock().await.reset();
+                        connect_udp(&mut udp_tx, &ip, config.udp_send_port).await;
                         backoff.reset();
                     }
-                    Signal::NewMode(mode) => {
-                        if let DsMode::Simulation = mode {
-                            let mut state = send_state.send().lock().await;
-                            state.reset_seqnum();
-                            state.disable();
-                            send_state.recv().lock().await.reset();
-                            udp_tx
-                                .connect("127.0.0.1:1110")
-                                .await
-                                .expect("Failed to connect to simulator socket");
-                            backoff.reset();
-                        }
+                    Signal::NewMode(DsMode::Simulation) => {
+                        let mut state = send_state.send().lock().await;
+                        state.reset_seqnum();
+                        state.disable();
+                        send_state.recv().lock().await.reset();
+                        send_state.stats().lock().await.reset();
+                        connect_udp(&mut udp_tx, "127.0.0.1", config.udp_send_port).await;
+                        backoff.reset();
                     }
                     _ => {}
                 },
@@ -118,7 +135,7 @@ pub(crate) async fn udp_conn(
 
     // I need the tokio extension for this, the futures extension to split codecs, and I can't import them both
     // Thanks for coordinating trait names to make using both nicely impossible
-    let fut = tokio::stream::StreamExt::timeout(udp_rx, Duration::from_secs(2)).map(Either::Left);
+    let fut = tokio::stream::StreamExt::timeout(udp_rx, config.recv_timeout).map(Either::Left);
     let mut stream = select(fut, rx.map(Either::Right));
 
     let mut connected = true;
@@ -126,36 +143,53 @@ pub(crate) async fn udp_conn(
         match item {
             Either::Left(packet) => match packet {
                 Ok(timeout_result) => match timeout_result {
-                    Ok(packet) => {
+                    Ok(Some(packet)) => {
                         if !connected {
                             connected = true;
                         }
-                        let (packet, _): (UdpResponsePacket, _) = packet;
+                        let fresh = state
+                            .stats()
+                            .lock()
+                            .await
+                            .received(packet.seqnum, Instant::now());
+                        // Packets reordered by the network would roll the state back
+                        if !fresh {
+                            continue;
+                        }
                         let mut _state = state.recv().lock().await;
 
                         if packet.need_date {
                             let local = Utc::now();
-                            let micros = local.naive_utc().timestamp_subsec_micros();
-                            let second = local.time().second() as u8;
-                            let minute = local.time().minute() as u8;
-                            let hour = local.time().hour() as u8;
-                            let day = local.date().day() as u8;
-                            let month = local.date().month0() as u8;
-                            let year = (local.date().year() - 1900) as u8;
+                            let micros = local.timestamp_subsec_micros();
+                            let second = local.second() as u8;
+                            let minute = local.minute() as u8;
+                            let hour = local.hour() as u8;
+                            let day = local.day() as u8;
+                            let month = local.month0() as u8;
+                            let year = (local.year() - 1900) as u8;
                             let tag = DTTag::new(micros, second, minute, hour, day, month, year);
-                            state.send().lock().await.queue_udp(UdpTag::DateTime(tag));
+
+                            let mut send = state.send().lock().await;
+                            send.queue_udp(UdpTag::DateTime(tag));
+                            if let Some(tz) = send.timezone().map(Timezone::new) {
+                                send.queue_udp(UdpTag::Timezone(tz));
+                            }
                         }
 
-                        if !tcp_connected {
+                        if tcp_task.is_none() {
                             let (tx, rx) = unbounded::<Signal>();
-                            tcp_tx = Some(tx);
                             let mode = *state.send().lock().await.ds_mode();
-                            if mode == DsMode::Normal {
-                                tokio::spawn(tcp_conn(state.clone(), target_ip.clone(), rx));
+                            let handle = if mode == DsMode::Normal {
+                                tokio::spawn(tcp_conn(state.clone(), target_ip.clone(), rx, config))
                             } else {
-                                tokio::spawn(tcp_conn(state.clone(), "127.0.0.1".to_string(), rx));
-                            }
-                            tcp_connected = true;
+                                tokio::spawn(tcp_conn(
+                                    state.clone(),
+                                    "127.0.0.1".to_string(),
+                                    rx,
+                                    config,
+                                ))
+                            };
+                            tcp_task = Some((tx, handle));
                         }
 
                         if packet.status.emergency_stopped() {
@@ -165,39 +199,75 @@ pub(crate) async fn udp_conn(
                             }
                         }
 
+                        _state.set_status(packet.status);
                         _state.set_trace(packet.trace);
                         _state.set_battery_voltage(packet.battery);
+                        if let Some(pdp_log) = packet.pdp_log {
+                            _state.set_pdp_log(pdp_log);
+                        }
+                        if let Some(cpu_info) = packet.cpu_info {
+                            _state.set_cpu_info(cpu_info);
+                        }
+                        if let Some(ram_info) = packet.ram_info {
+                            _state.set_ram_info(ram_info);
+                        }
+                        if let Some(disk_info) = packet.disk_info {
+                            _state.set_disk_info(disk_info);
+                        }
+                        if let Some(can_metrics) = packet.can_metrics {
+                            _state.set_can_metrics(can_metrics);
+                        }
+                        if !packet.joystick_outputs.is_empty() {
+                            _state.set_joystick_outputs(packet.joystick_outputs);
+                        }
+                        for tag in packet.unknown_tags {
+                            _state.update_unknown_tag(tag);
+                        }
                     }
+                    Ok(None) => {}
                     Err(e) => println!("Error decoding packet: {:?}", e),
                 },
                 Err(_) => {
                     if connected {
                         println!("RIO disconnected");
                         state.recv().lock().await.reset();
+                        state.stats().lock().await.reset_sequence();
                         connected = false;
                     }
+
+                    // A hostname such as roboRIO-TEAM-FRC.local may not resolve until the roboRIO has booted,
+                    // or may resolve to different addresses once it has
+                    if *state.send().lock().await.ds_mode() == DsMode::Normal {
+                        if let Ok(addrs) = resolve(&target_ip).await {
+                            route.set_robot(addrs);
+                        }
+                    }
                 }
             },
             Either::Right(sig) => match sig {
-                Signal::Disconnect => return Ok(()),
+                Signal::Disconnect => {
+                    stop_tcp(&mut tcp_task).await;
+                    return Ok(());
+                }
                 Signal::NewTarget(ref target) => {
-                    if let Some(ref tcp_tx) = tcp_tx {
-                        let _ = tcp_tx.unbounded_send(Signal::Disconnect);
-                        tcp_connected = false;
-                    }
+                    stop_tcp(&mut tcp_task).await;
 
                     target_ip = target.clone();
+                    route_to(&route, &target_ip).await;
 
                     fwd_tx.unbounded_send(sig)?;
                 }
                 Signal::NewMode(mode) => {
                     let current_mode = *state.send().lock().await.ds_mode();
                     if mode != current_mode {
-                        if let Some(ref tcp_tx) = tcp_tx {
-                            let _ = tcp_tx.unbounded_send(Signal::Disconnect);
-                            tcp_connected = false;
-                        }
+                        stop_tcp(&mut tcp_task).await;
                         state.send().lock().await.set_ds_mode(mode);
+                        // The simulator responds from localhost
+                        if mode == DsMode::Simulation {
+                            route.set_robot(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]);
+                        } else {
+                            route_to(&route, &target_ip).await;
+                        }
                         if mode == DsMode::Normal {
                             println!("Exiting simulation mode");
                             fwd_tx.unbounded_send(Signal::NewTarget(target_ip.clone()))?;
@@ -211,71 +281,285 @@ pub(crate) async fn udp_conn(
     Ok(())
 }
 
-/// tokio task for all TCP communications
+/// Sends control packets from `socket` to `port` on `target`
 ///
-/// This task will decode incoming TCP packets, and call the tcp consumer defined in `state` if it exists.
-/// It will also accept packets to send from a channel set in `state`, for tasks such as defining game data.
+/// Failing to resolve the target isn't fatal, packets fail to send until the target changes and the failures are backed off.
+async fn connect_udp(socket: &mut UdpSocket, target: &str, port: u16) {
+    if let Err(e) = socket.connect((target, port)).await {
+        println!("Failed to connect to {}: {}", target, e);
+    }
+}
+
+/// Resolves the addresses of the roboRIO at `target`, which is usually an ip but may be a hostname
+async fn resolve(target: &str) -> io::Result<Vec<IpAddr>> {
+    let mut addrs = net::lookup_host((target, 0))
+        .await?
+        .map(|addr| addr.ip())
+        .collect::<Vec<_>>();
+    addrs.dedup();
+    Ok(addrs)
+}
+
+/// Routes the packets sent by the roboRIO at `target` to this driver station
+async fn route_to(route: &Subscription, target: &str) {
+    match resolve(target).await {
+        Ok(addrs) => route.set_robot(addrs),
+        Err(e) => {
+            // While it's the only driver station using the port, packets are still received from any address
+            println!("Failed to resolve {}: {}", target, e);
+            route.set_robot(Vec::new());
+        }
+    }
+}
+
+/// Tells the TCP task to disconnect, and waits for it to finish so that it can't race with the task that replaces it
+async fn stop_tcp(task: &mut Option<(UnboundedSender<Signal>, JoinHandle<Result<()>>)>) {
+    if let Some((tx, handle)) = task.take() {
+        let _ = tx.unbounded_send(Signal::Disconnect);
+        let _ = handle.await;
+    }
+}
+
+/// tokio task supervising TCP communications with the roboRIO
+///
+/// Failed connection attempts are retried with an exponential backoff, as are connections that are lost, until the task is
+/// told to disconnect. The state of the connection is recorded in `state` as it changes.
 pub(crate) async fn tcp_conn(
     state: Arc<DsState>,
     target_ip: String,
-    rx: UnboundedReceiver<Signal>,
+    mut rx: UnboundedReceiver<Signal>,
+    config: Config,
+) -> Result<()> {
+    let addr = format!("{}:{}", target_ip, config.tcp_port);
+    let mut backoff = ExponentialBackoff::new(config.max_backoff);
+    state
+        .tcp()
+        .lock()
+        .await
+        .set_connection_state(TcpConnectionState::Connecting);
+
+    loop {
+        // The signal to disconnect can arrive while waiting to retry, so it's raced against the attempt to connect
+        let attempt = Box::pin(backoff.run(connect_tcp(&addr, config.bind_address)));
+        let conn = match future::select(attempt, rx.next()).await {
+            Either::Left((Ok(conn), _)) => conn,
+            Either::Left((Err((e, _)), _)) => {
+                state
+                    .tcp()
+                    .lock()
+                    .await
+                    .set_connection_state(TcpConnectionState::Failed(e.to_string()));
+                continue;
+            }
+            Either::Right(_) => break,
+        };
+
+        match tcp_session(&state, conn, &mut rx).await {
+            Ok(()) => break,
+            // The session has already reported the failure, but a robot that closes connections as soon as they're
+            // made shouldn't be reconnected to in a tight loop
+            Err(_) => {
+                backoff.failed();
+            }
+        }
+    }
+
+    let mut state = state.tcp().lock().await;
+    state.set_tcp_tx(None);
+    state.set_connection_state(TcpConnectionState::Disconnected);
+    Ok(())
+}
+
+/// Connects to `addr`, from `bind_address` unless it is the unspecified address
+async fn connect_tcp(addr: &str, bind_address: IpAddr) -> io::Result<TcpStream> {
+    if bind_address.is_unspecified() {
+        return TcpStream::connect(addr).await;
+    }
+
+    let addr = net::lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "Target has no addresses"))?;
+    let builder = match bind_address {
+        IpAddr::V4(_) => TcpBuilder::new_v4()?,
+        IpAddr::V6(_) => TcpBuilder::new_v6()?,
+    };
+    builder.bind((bind_address, 0))?;
+
+    TcpStream::connect_std(builder.to_tcp_stream()?, &addr).await
+}
+
+/// Handles a single TCP connection to the roboRIO
+///
+/// This will decode incoming TCP packets, and call the tcp consumer defined in `state` if it exists.
+/// It will also accept packets to send from a channel set in `state`, for tasks such as defining game data.
+/// Returns once told to disconnect, or with an error if the connection is lost.
+async fn tcp_session(
+    state: &Arc<DsState>,
+    conn: TcpStream,
+    rx: &mut UnboundedReceiver<Signal>,
 ) -> Result<()> {
-    let conn = TcpStream::connect(&format!("{}:1740", target_ip)).await?;
     let codec = DsTcpCodec.framed(conn);
     let (mut codec_tx, codec_rx) = codec.split();
 
-    let (tag_tx, tag_rx) = unbounded::<TcpTag>();
-    state.tcp().lock().await.set_tcp_tx(Some(tag_tx));
+    let (tag_tx, mut tag_rx) = unbounded::<TcpTag>();
+    {
+        let mut state = state.tcp().lock().await;
+        state.set_tcp_tx(Some(tag_tx));
+        state.set_connection_state(TcpConnectionState::Connected);
+    }
+
+    // Joystick descriptors are checked periodically, and sent whenever they change
+    let descriptor_interval = time::interval(Duration::from_millis(500));
+    let mut sent_descriptors = Vec::new();
 
-    let stream = select(codec_rx.map(Either::Left), rx.map(Either::Right));
-    let mut stream = select(stream.map(Either::Left), tag_rx.map(Either::Right));
+    // The end of the inbound stream is marked with None, as the interval would otherwise keep the merged stream alive
+    let inbound = codec_rx.map(Some).chain(stream::once(future::ready(None)));
+    // Signals are treated as a request to disconnect, as is the supervisor dropping its sender
+    let signals = rx.map(Some).chain(stream::once(future::ready(None)));
+    let stream = select(inbound.map(Either::Left), signals.map(Either::Right));
+    let outbound = select(
+        (&mut tag_rx).map(Either::Left),
+        descriptor_interval.map(Either::Right),
+    );
+    let mut stream = select(stream.map(Either::Left), outbound.map(Either::Right));
+
+    let tcp = state.tcp();
+    // A tag taken from the queue that couldn't be sent, which is buffered along with the rest of the queue
+    let mut unsent = None;
+    let result = 'session: loop {
+        let msg = match stream.next().await {
+            Some(msg) => msg,
+            None => break Ok(()),
+        };
 
-    let state = state.tcp();
-    while let Some(msg) = stream.next().await {
         match msg {
             Either::Left(left) => match left {
-                Either::Left(packet) => {
+                Either::Left(Some(packet)) => {
                     if let Ok(packet) = packet {
-                        let mut state = state.lock().await;
+                        let mut state = tcp.lock().await;
+                        match packet {
+                            TcpPacket::VersionInfo(ref info) => state.update_version(info.clone()),
+                            TcpPacket::DisableFaults(faults) => state.update_disable_faults(faults),
+                            TcpPacket::RailFaults(faults) => state.update_rail_faults(faults),
+                            _ => {}
+                        }
                         if let Some(ref mut consumer) = state.tcp_consumer {
                             consumer(packet);
                         }
                     }
                 }
-                Either::Right(_) => {
-                    state.lock().await.set_tcp_tx(None);
-                }
+                Either::Left(None) => break Err(format_err!("Connection closed by the roboRIO")),
+                Either::Right(_) => break Ok(()),
             },
-            Either::Right(tag) => {
-                let _ = codec_tx.send(tag).await;
+            Either::Right(Either::Left(tag)) => {
+                if let Err(e) = codec_tx.send(tag.clone()).await {
+                    unsent = Some(tag);
+                    break Err(e);
+                }
+                if let TcpTag::JoystickDescriptor(ref descriptor) = tag {
+                    state
+                        .send()
+                        .lock()
+                        .await
+                        .set_joystick_counts(std::slice::from_ref(descriptor));
+                }
+            }
+            Either::Right(Either::Right(_)) => {
+                let ports = state.send().lock().await.joystick_counts().to_vec();
+                let descriptors = tcp.lock().await.joystick_descriptors(&ports);
+                if descriptors != sent_descriptors {
+                    for descriptor in &descriptors {
+                        if let Err(e) = codec_tx
+                            .send(TcpTag::JoystickDescriptor(descriptor.clone()))
+                            .await
+                        {
+                            break 'session Err(e);
+                        }
+                    }
+                    // Joysticks are sent with the layout robot code now expects for them
+                    state.send().lock().await.set_joystick_counts(&descriptors);
+                    sent_descriptors = descriptors;
+                }
             }
         }
+    };
+    drop(stream);
+
+    // Tags that were queued for this connection but not sent are kept for the next one
+    let mut state = tcp.lock().await;
+    if let Err(ref e) = result {
+        state.set_connection_state(TcpConnectionState::Failed(e.to_string()));
     }
-    Ok(())
+    state.set_tcp_tx(None);
+    tag_rx.close();
+    let queued = iter::from_fn(|| tag_rx.next().now_or_never().flatten());
+    state.requeue_tcp(unsent.into_iter().chain(queued));
+
+    result
 }
 
-pub(crate) async fn sim_conn(tx: UnboundedSender<Signal>) -> Result<()> {
-    use tokio::time::timeout;
-    const SOCK_TIMEOUT: Duration = Duration::from_millis(250);
+/// tokio task switching the driver station into simulation mode while a simulator announces itself, and back out once it stops
+pub(crate) async fn sim_conn(tx: UnboundedSender<Signal>, mut sim_rx: UnboundedReceiver<BytesMut>) {
+    /// How long the simulator can go without announcing itself before it's considered closed
+    const SIM_TIMEOUT: Duration = Duration::from_millis(250);
 
-    let mut sock = UdpSocket::bind("127.0.0.1:1135").await?;
-    let mut buf = [0];
-    let mut opmode = DsMode::Normal;
-    loop {
-        match timeout(SOCK_TIMEOUT, sock.recv(&mut buf[..])).await {
-            Ok(_) => {
-                if opmode != DsMode::Simulation {
-                    opmode = DsMode::Simulation;
-                    tx.unbounded_send(Signal::NewMode(DsMode::Simulation))
-                        .unwrap();
-                }
-            }
-            Err(_) => {
-                if opmode != DsMode::Normal {
-                    opmode = DsMode::Normal;
-                    tx.unbounded_send(Signal::NewMode(DsMode::Normal)).unwrap();
+    // The packets end when the driver station is dropped, after which there's nothing left to notify
+    while sim_rx.next().await.is_some() {
+        let _ = tx.unbounded_send(Signal::NewMode(DsMode::Simulation));
+        while let Ok(Some(_)) = time::timeout(SIM_TIMEOUT, sim_rx.next()).await {}
+        let _ = tx.unbounded_send(Signal::NewMode(DsMode::Normal));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Alliance;
+    use tokio::net::TcpListener;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn back_off_when_session_ends() {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+            let config = Config {
+                tcp_port: listener.local_addr().unwrap().port(),
+                ..Config::default()
+            };
+            let state = Arc::new(DsState::new(Alliance::new_red(1)));
+            let (tx, rx) = unbounded();
+            let conn = tokio::spawn(tcp_conn(
+                state.clone(),
+                Ipv4Addr::LOCALHOST.to_string(),
+                rx,
+                config,
+            ));
+
+            // The robot accepts the connection, then closes it straight away
+            let (sock, _) = listener.accept().await.unwrap();
+            drop(sock);
+
+            let closed = Instant::now();
+            loop {
+                if let TcpConnectionState::Failed(_) = state.tcp().lock().await.connection_state() {
+                    break;
                 }
+                assert!(closed.elapsed() < Duration::from_millis(500));
+                time::delay_for(Duration::from_millis(1)).await;
             }
-        }
+
+            // Reconnecting waits for at least a second
+            let retry = time::timeout(Duration::from_millis(500), listener.accept());
+            assert!(retry.await.is_err());
+
+            drop(tx);
+            conn.await.unwrap().unwrap();
+            assert_eq!(
+                *state.tcp().lock().await.connection_state(),
+                TcpConnectionState::Disconnected
+            );
+        });
     }
 }
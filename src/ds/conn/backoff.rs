@@ -24,7 +24,6 @@ impl ExponentialBackoff {
         fut: impl Future<Output = Result<O, E>>,
     ) -> Result<O, (E, bool)> {
         if let Some(timeout) = self.timeout {
-            println!("Backoff: waiting {:?}", timeout);
             time::delay_for(timeout).await;
         }
         match fut.await {
@@ -32,15 +31,20 @@ impl ExponentialBackoff {
                 self.reset();
                 Ok(out)
             }
-            Err(e) => {
-                let disconnected = self.attempt == 0;
-                self.calculate_wait();
-                self.attempt += 1;
-                Err((e, disconnected))
-            }
+            Err(e) => Err((e, self.failed())),
         }
     }
 
+    /// Records a failed attempt and calculates how long to wait before the next one.
+    /// Returns whether this was the first failure since the backoff was last reset
+    pub fn failed(&mut self) -> bool {
+        let disconnected = self.attempt == 0;
+        self.calculate_wait();
+        // Retries can go on indefinitely, and the wait stops growing once it reaches the max anyway
+        self.attempt = self.attempt.saturating_add(1);
+        disconnected
+    }
+
     pub fn reset(&mut self) {
         self.use_max = false;
         self.attempt = 0;
@@ -55,9 +59,11 @@ impl ExponentialBackoff {
 
         let random_delay = Duration::from_millis(thread_rng().gen_range(1, 1000));
 
-        let backoff_seconds = 2u64.pow(self.attempt as u32);
+        let backoff = Duration::from_secs(2u64.saturating_pow(self.attempt as u32));
 
-        let delay = (Duration::new(backoff_seconds, 0) + random_delay).min(self.max_timeout);
+        let delay = backoff
+            .checked_add(random_delay)
+            .map_or(self.max_timeout, |delay| delay.min(self.max_timeout));
         if delay == self.max_timeout {
             self.use_max = true;
         }
@@ -65,3 +71,30 @@ impl ExponentialBackoff {
         self.timeout = Some(delay);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn repeated_failures() {
+        let max = Duration::from_secs(5);
+        let mut backoff = ExponentialBackoff::new(max);
+
+        assert!(backoff.failed());
+        for _ in 0..1000 {
+            assert!(!backoff.failed());
+        }
+        assert_eq!(backoff.timeout, Some(max));
+
+        backoff.reset();
+        assert!(backoff.failed());
+
+        // A max too long to ever be reached can't overflow the wait either
+        let mut backoff = ExponentialBackoff::new(Duration::from_secs(u64::MAX));
+        for _ in 0..1000 {
+            backoff.failed();
+        }
+        assert_eq!(backoff.timeout, Some(Duration::from_secs(u64::MAX)));
+    }
+}
@@ -0,0 +1,295 @@
+//! Sharing of the ports that the roboRIO and simulator send packets to between every driver station in the process
+//!
+//! Each driver station receives on its own handle to the shared socket, as a task on the runtime that it runs on, and
+//! routes whatever packets it reads to the driver stations that they're for, by the address that sent them. While only
+//! one driver station is using the port, it receives packets from any address, as it did before the port was shared.
+//! The port is released once no driver stations are using it.
+//!
+//! A driver station bound to a specific address shares a socket that's already bound to the unspecified address on the
+//! same port, as that socket receives its packets too. The reverse isn't possible: the unspecified address can't be
+//! bound while a specific address is using the port.
+
+use bytes::BytesMut;
+use futures_channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures_channel::oneshot;
+use futures_util::future::{self, Either};
+use futures_util::pin_mut;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tokio::runtime::Handle;
+
+/// The sockets currently in use, keyed by the local address that was requested for them
+static SOCKETS: Mutex<Vec<(SocketAddr, Arc<Routes>)>> = Mutex::new(Vec::new());
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// The driver stations receiving on one local address
+struct Routes {
+    /// The socket that each driver station receives on a handle to
+    socket: std::net::UdpSocket,
+    /// The address that the socket is bound to, which has the port picked by the OS if port 0 was requested
+    local: SocketAddr,
+    routes: Mutex<Vec<Route>>,
+}
+
+struct Route {
+    id: u64,
+    /// The addresses of the roboRIO that packets are accepted from
+    robot: Vec<IpAddr>,
+    tx: UnboundedSender<BytesMut>,
+}
+
+/// A driver station's registration with a shared receiving socket. The registration is removed when this is dropped
+pub(crate) struct Subscription {
+    id: u64,
+    routes: Arc<Routes>,
+    /// Stops the task receiving for this driver station when dropped
+    _stop: oneshot::Sender<()>,
+}
+
+impl Subscription {
+    /// Changes the addresses of the roboRIO that packets are accepted from
+    pub fn set_robot(&self, robot: Vec<IpAddr>) {
+        let mut routes = self.routes.routes.lock().unwrap();
+        if let Some(route) = routes.iter_mut().find(|route| route.id == self.id) {
+            route.robot = robot;
+        }
+    }
+
+    /// Returns the address that the shared socket is bound to
+    #[cfg(test)]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.routes.local
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // Subscribing holds this lock too, so nothing can start using the socket while it's being released
+        let mut sockets = SOCKETS.lock().unwrap();
+        let mut routes = self.routes.routes.lock().unwrap();
+        routes.retain(|route| route.id != self.id);
+        if routes.is_empty() {
+            sockets.retain(|(_, r)| !Arc::ptr_eq(r, &self.routes));
+        }
+    }
+}
+
+/// Starts receiving the packets sent to `local` by `robot` on the runtime of `handle`, binding `local` if no other
+/// driver station is using it
+pub(crate) fn subscribe(
+    local: SocketAddr,
+    robot: Vec<IpAddr>,
+    handle: &Handle,
+) -> io::Result<(Subscription, UnboundedReceiver<BytesMut>)> {
+    let mut sockets = SOCKETS.lock().unwrap();
+
+    let shared = sockets
+        .iter()
+        .find(|(addr, _)| *addr == local)
+        .or_else(|| {
+            sockets
+                .iter()
+                .find(|(_, routes)| shares_unspecified(routes.local, local))
+        })
+        .map(|(_, routes)| routes.clone());
+    let routes = match shared {
+        Some(ref routes) => routes.clone(),
+        None => {
+            let socket = std::net::UdpSocket::bind(local)?;
+            Arc::new(Routes {
+                local: socket.local_addr()?,
+                socket,
+                routes: Mutex::new(Vec::new()),
+            })
+        }
+    };
+
+    let socket = routes.socket.try_clone()?;
+    let socket = handle.enter(|| UdpSocket::from_std(socket))?;
+    if shared.is_none() {
+        sockets.push((local, routes.clone()));
+    }
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let (tx, rx) = unbounded();
+    let (stop_tx, stop_rx) = oneshot::channel();
+    routes.routes.lock().unwrap().push(Route { id, robot, tx });
+    handle.spawn(receive(socket, routes.clone(), stop_rx));
+
+    let subscription = Subscription {
+        id,
+        routes,
+        _stop: stop_tx,
+    };
+    Ok((subscription, rx))
+}
+
+/// Whether a driver station asking for `local` can use the socket bound to `bound`, which receives its packets too
+fn shares_unspecified(bound: SocketAddr, local: SocketAddr) -> bool {
+    local.port() != 0
+        && bound.port() == local.port()
+        && bound.ip().is_unspecified()
+        && bound.is_ipv4() == local.is_ipv4()
+}
+
+/// Receives packets on `socket` and forwards them to the driver stations in `routes`, until `stop` is dropped
+async fn receive(mut socket: UdpSocket, routes: Arc<Routes>, mut stop: oneshot::Receiver<()>) {
+    let mut buf = [0u8; 1500];
+    // Errors are reported once until a packet is received again, rather than for every failed receive
+    let mut failing = false;
+
+    loop {
+        let received = {
+            let recv = socket.recv_from(&mut buf);
+            pin_mut!(recv);
+            match future::select(recv, &mut stop).await {
+                Either::Left((received, _)) => received,
+                Either::Right(_) => return,
+            }
+        };
+        let (len, from) = match received {
+            Ok(received) => received,
+            Err(e) => {
+                if !failing {
+                    println!("Error receiving on {}: {}", routes.local, e);
+                    failing = true;
+                }
+                continue;
+            }
+        };
+        failing = false;
+
+        let routes = routes.routes.lock().unwrap();
+        let from = canonical(from.ip());
+        let mut matching = routes
+            .iter()
+            .filter(|route| route.robot.iter().any(|&robot| canonical(robot) == from))
+            .peekable();
+
+        if matching.peek().is_some() {
+            for route in matching {
+                let _ = route.tx.unbounded_send(BytesMut::from(&buf[..len]));
+            }
+        } else if let [route] = &routes[..] {
+            // The roboRIO may not have resolved yet, or may reply from an address that it didn't resolve to
+            let _ = route.tx.unbounded_send(BytesMut::from(&buf[..len]));
+        }
+    }
+}
+
+/// Converts IPv4-mapped IPv6 addresses to IPv4, so that they match the address they map to
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{Ipv4Addr, UdpSocket};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tokio::runtime::Runtime;
+
+    /// Waits up to a few seconds for the next packet from `rx`
+    fn recv(rx: &mut UnboundedReceiver<BytesMut>) -> Option<BytesMut> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if let Ok(buf) = rx.try_recv() {
+                return Some(buf);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        None
+    }
+
+    /// Waits up to a few seconds for `local` to be released by the tasks that were receiving on it
+    fn wait_for_release(local: SocketAddr) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while UdpSocket::bind(local).is_err() {
+            assert!(Instant::now() < deadline, "Port wasn't released");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn routes_by_source() {
+        let rt = Runtime::new().unwrap();
+        let robot = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+        let (first, mut first_rx) = subscribe(
+            (Ipv4Addr::LOCALHOST, 0).into(),
+            vec![other, localhost],
+            rt.handle(),
+        )
+        .unwrap();
+        let local = first.local_addr();
+
+        // A single driver station receives packets from any address
+        first.set_robot(vec![other]);
+        robot.send_to(&[0], local).unwrap();
+        assert_eq!(&recv(&mut first_rx).unwrap()[..], &[0]);
+
+        // Once the port is shared, packets only go to the driver station with a matching address
+        first.set_robot(vec![other, localhost]);
+        let (second, mut second_rx) =
+            subscribe((Ipv4Addr::LOCALHOST, 0).into(), vec![], rt.handle()).unwrap();
+        assert_eq!(second.local_addr(), local);
+
+        robot.send_to(&[1], local).unwrap();
+        assert_eq!(&recv(&mut first_rx).unwrap()[..], &[1]);
+
+        first.set_robot(vec![]);
+        second.set_robot(vec![localhost]);
+        robot.send_to(&[2], local).unwrap();
+        assert_eq!(&recv(&mut second_rx).unwrap()[..], &[2]);
+
+        // Packets that match neither are dropped, rather than guessing which driver station they're for
+        let stranger = UdpSocket::bind((Ipv4Addr::new(127, 0, 0, 2), 0)).unwrap();
+        stranger.send_to(&[3], local).unwrap();
+        robot.send_to(&[4], local).unwrap();
+        assert_eq!(&recv(&mut second_rx).unwrap()[..], &[4]);
+        assert!(first_rx.try_recv().is_err());
+
+        // The port is released once neither driver station is using it
+        drop((first, second));
+        wait_for_release(local);
+    }
+
+    #[test]
+    fn share_unspecified_address() {
+        let rt = Runtime::new().unwrap();
+        let robot = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let (any, _any_rx) =
+            subscribe((Ipv4Addr::UNSPECIFIED, 0).into(), vec![], rt.handle()).unwrap();
+        let port = any.local_addr().port();
+
+        // A driver station bound to one interface receives through the socket bound to all of them
+        let (loopback, mut loopback_rx) = subscribe(
+            (Ipv4Addr::LOCALHOST, port).into(),
+            vec![localhost],
+            rt.handle(),
+        )
+        .unwrap();
+        assert_eq!(loopback.local_addr(), any.local_addr());
+        robot.send_to(&[0], (Ipv4Addr::LOCALHOST, port)).unwrap();
+        assert_eq!(&recv(&mut loopback_rx).unwrap()[..], &[0]);
+
+        // The unspecified address can't share a socket bound to one interface
+        drop((any, loopback));
+        wait_for_release((Ipv4Addr::UNSPECIFIED, port).into());
+        let (_loopback, _) =
+            subscribe((Ipv4Addr::LOCALHOST, port).into(), vec![], rt.handle()).unwrap();
+        assert!(subscribe((Ipv4Addr::UNSPECIFIED, port).into(), vec![], rt.handle()).is_err());
+    }
+}
@@ -2,17 +2,22 @@ use super::JoystickValue;
 
 use crate::ds::state::recv::{RecvState, TcpState};
 use crate::ds::state::send::SendState;
+use crate::ds::state::stats::PacketTracker;
 use crate::proto::udp::inbound::types::Status;
 use crate::proto::udp::outbound::types::{Alliance, Control};
-use crate::TcpPacket;
+use crate::{JoystickDescriptor, TcpPacket};
 use std::fmt::Debug;
 use tokio::sync::Mutex;
 
 mod recv;
-mod send;
+pub(crate) mod send;
+mod stats;
+
+pub use self::stats::ConnectionStats;
 
 type JoystickSupplier = dyn Fn() -> Vec<Vec<JoystickValue>> + Send + Sync + 'static;
 type TcpConsumer = dyn FnMut(TcpPacket) + Send + Sync + 'static;
+type JoystickDescriptorSupplier = dyn Fn() -> Vec<JoystickDescriptor> + Send + Sync + 'static;
 
 /// The operating mode of the driver station
 ///
@@ -32,6 +37,8 @@ pub struct DsState {
     recv_state: Mutex<RecvState>,
     /// The state associated with the TCP socket
     tcp_state: Mutex<TcpState>,
+    /// Latency and packet loss tracking for the UDP connection
+    stats_state: Mutex<PacketTracker>,
 }
 
 impl DsState {
@@ -39,11 +46,13 @@ impl DsState {
         let send_state = Mutex::new(SendState::new(alliance));
         let recv_state = Mutex::new(RecvState::new());
         let tcp_state = Mutex::new(TcpState::new());
+        let stats_state = Mutex::new(PacketTracker::new());
 
         DsState {
             send_state,
             recv_state,
             tcp_state,
+            stats_state,
         }
     }
 
@@ -58,10 +67,28 @@ impl DsState {
     pub fn tcp(&self) -> &Mutex<TcpState> {
         &self.tcp_state
     }
+
+    /// No other locks should be acquired while this one is held
+    pub fn stats(&self) -> &Mutex<PacketTracker> {
+        &self.stats_state
+    }
+}
+
+/// The state of the TCP connection to the roboRIO
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TcpConnectionState {
+    /// No connection is being attempted, because the roboRIO hasn't been found over UDP yet
+    Disconnected,
+    /// The first attempt at connecting is in progress
+    Connecting,
+    /// TCP is connected, and packets are being exchanged with the roboRIO
+    Connected,
+    /// The last attempt at connecting failed, or the connection was lost, with the given error. Connecting will be retried
+    Failed(String),
 }
 
 /// Represents the current Mode that the robot is in. the `Mode` of the robot is considered separately from whether it is enabled or not
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Mode {
     Autonomous,
     Teleoperated,
@@ -70,24 +97,44 @@ pub enum Mode {
 
 impl Mode {
     /// Decodes the mode of the robot from the given status byte
+    ///
+    /// The mode is stored in the lower two bits of the byte, `None` is returned if they hold a value that doesn't correspond to a mode.
     pub fn from_status(status: Status) -> Option<Mode> {
-        if status & Status::TELEOP == Status::TELEOP {
-            Some(Mode::Teleoperated)
-        } else if status & Status::AUTO == Status::AUTO {
-            Some(Mode::Autonomous)
-        } else if status & Status::TEST == Status::TEST {
-            Some(Mode::Test)
-        } else {
-            None
+        match status.bits() & 0b11 {
+            0b00 => Some(Mode::Teleoperated),
+            0b01 => Some(Mode::Test),
+            0b10 => Some(Mode::Autonomous),
+            _ => None,
         }
     }
 
     /// Converts this `Mode` into a `Control` byte that can be modified for encoding the control packet.
-    fn to_control(&self) -> Control {
-        match *self {
+    fn to_control(self) -> Control {
+        match self {
             Mode::Teleoperated => Control::TELEOP,
             Mode::Autonomous => Control::AUTO,
             Mode::Test => Control::TEST,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mode_from_status() {
+        let status = |bits| Status::from_bits_truncate(bits);
+
+        assert_eq!(
+            Mode::from_status(status(0b0000_0100)),
+            Some(Mode::Teleoperated)
+        );
+        assert_eq!(Mode::from_status(status(0b0000_0101)), Some(Mode::Test));
+        assert_eq!(
+            Mode::from_status(status(0b0001_0110)),
+            Some(Mode::Autonomous)
+        );
+        assert_eq!(Mode::from_status(status(0b0000_0011)), None);
+    }
+}
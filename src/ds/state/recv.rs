@@ -1,10 +1,17 @@
-use crate::ds::state::TcpConsumer;
+use crate::ds::state::send::JoystickCounts;
+use crate::ds::state::{JoystickDescriptorSupplier, TcpConnectionState, TcpConsumer};
 use crate::proto::tcp::outbound::TcpTag;
+use crate::proto::udp::inbound::types::tags::{
+    CanMetrics, CpuInfo, DiskInfo, JoystickOutput, PdpLog, RamInfo, UnknownTag,
+};
 use crate::proto::udp::inbound::types::*;
-use crate::Result;
-use crate::TcpPacket;
-use failure::format_err;
+use crate::{DisableFaults, JoystickDescriptor, RailFaults, TcpPacket, VersionInfo};
+use crate::{GameData, MatchInfo};
 use futures_channel::mpsc::UnboundedSender;
+use std::collections::VecDeque;
+
+/// The most TCP tags that are buffered while the roboRIO is disconnected, older tags are dropped first
+const MAX_BUFFERED_TCP: usize = 64;
 
 /// All the data received from roboRIO UDP status packets that isn't already encoded in the send state
 pub struct RecvState {
@@ -12,12 +19,38 @@ pub struct RecvState {
     battery_voltage: f32,
     /// A bitflags struct that can be used to query the state of various aspects of the RIO
     trace: Trace,
+    /// The mode and state that the roboRIO last reported itself to be in, `None` if no packet has been received
+    status: Option<Status>,
+    /// The most recent power distribution telemetry, if the roboRIO has sent any
+    pdp_log: Option<PdpLog>,
+    /// The most recent CPU usage of the roboRIO
+    cpu_info: Option<CpuInfo>,
+    /// The most recent memory usage of the roboRIO
+    ram_info: Option<RamInfo>,
+    /// The most recent free disk space of the roboRIO
+    disk_info: Option<DiskInfo>,
+    /// The most recent CAN bus metrics of the roboRIO
+    can_metrics: Option<CanMetrics>,
+    /// The most recent joystick outputs requested by robot code, indexed by joystick port
+    joystick_outputs: Vec<JoystickOutput>,
+    /// The most recent contents of each tag that this crate doesn't decode, ordered by id
+    unknown_tags: Vec<UnknownTag>,
+    /// The error that stopped the UDP connection, which isn't reset as nothing is received after it
+    udp_error: Option<String>,
 }
 
 impl RecvState {
     pub fn reset(&mut self) {
         self.battery_voltage = 0f32;
         self.trace = Trace::empty();
+        self.status = None;
+        self.pdp_log = None;
+        self.cpu_info = None;
+        self.ram_info = None;
+        self.disk_info = None;
+        self.can_metrics = None;
+        self.joystick_outputs.clear();
+        self.unknown_tags.clear();
     }
 }
 
@@ -25,8 +58,30 @@ impl RecvState {
 pub struct TcpState {
     /// An optional callback that should be notified upon incoming packets being decoded
     pub tcp_consumer: Option<Box<TcpConsumer>>,
-    /// A channel of packets that should be sent to the roboRIO
+    /// A channel of packets that should be sent to the roboRIO, set while TCP is connected
     pending_tcp: Option<UnboundedSender<TcpTag>>,
+    /// The state of the TCP connection, as last updated by the tcp_conn task
+    connection_state: TcpConnectionState,
+    /// One-shot tags queued while TCP wasn't connected, sent when it next connects
+    buffered_tcp: VecDeque<TcpTag>,
+    /// The most recent game specific message, sent whenever TCP connects
+    game_data: Option<GameData>,
+    /// The most recent match info, sent whenever TCP connects
+    match_info: Option<MatchInfo>,
+    /// Joystick descriptors queued by the user, sent whenever TCP connects
+    descriptors: Vec<JoystickDescriptor>,
+    /// An optional source for descriptors of the joysticks plugged into each port
+    descriptor_supplier: Option<Box<JoystickDescriptorSupplier>>,
+    /// The versions of software and devices most recently reported by the roboRIO
+    versions: Vec<VersionInfo>,
+    /// Disable faults counted since the driver station was started
+    disable_faults: DisableFaults,
+    /// The disable fault counters as last reported by the roboRIO
+    last_disable_faults: Option<DisableFaults>,
+    /// Power rail faults counted since the driver station was started
+    rail_faults: RailFaults,
+    /// The power rail fault counters as last reported by the roboRIO
+    last_rail_faults: Option<RailFaults>,
 }
 
 impl TcpState {
@@ -34,20 +89,216 @@ impl TcpState {
         TcpState {
             tcp_consumer: None,
             pending_tcp: None,
+            connection_state: TcpConnectionState::Disconnected,
+            buffered_tcp: VecDeque::new(),
+            game_data: None,
+            match_info: None,
+            descriptors: Vec::new(),
+            descriptor_supplier: None,
+            versions: Vec::new(),
+            disable_faults: DisableFaults::default(),
+            last_disable_faults: None,
+            rail_faults: RailFaults::default(),
+            last_rail_faults: None,
         }
     }
 
-    pub fn queue_tcp(&self, tag: TcpTag) -> Result<()> {
+    /// Queues a tag to be sent to the roboRIO
+    ///
+    /// Game data, match info and joystick descriptors are remembered and sent again every time TCP connects.
+    /// Other tags are buffered if TCP isn't connected, and sent when it next connects.
+    pub fn queue_tcp(&mut self, tag: TcpTag) {
+        let session = match tag {
+            TcpTag::GameData(ref gd) => {
+                self.game_data = Some(gd.clone());
+                true
+            }
+            TcpTag::MatchInfo(ref mi) => {
+                self.match_info = Some(mi.clone());
+                true
+            }
+            TcpTag::JoystickDescriptor(ref jd) => {
+                match self.descriptors.iter_mut().find(|d| d.index == jd.index) {
+                    Some(existing) => *existing = jd.clone(),
+                    None => self.descriptors.push(jd.clone()),
+                }
+                true
+            }
+            _ => false,
+        };
+
         // pending_tcp is set by the tcp_conn function when it connects.
-        self.pending_tcp
-            .clone()
-            .ok_or_else(|| format_err!("TCP task not spawned."))
-            .and_then(move |tx| tx.unbounded_send(tag).map_err(|e| e.into()))
-            .map(|_| ())
+        let tag = match self.pending_tcp {
+            Some(ref tx) => match tx.unbounded_send(tag) {
+                Ok(()) => return,
+                Err(e) => {
+                    // The connection dropped without clearing the sender
+                    self.pending_tcp = None;
+                    e.into_inner()
+                }
+            },
+            None => tag,
+        };
+
+        // Session tags are sent again on connect, so don't need to be buffered
+        if !session {
+            self.buffer_tcp(tag);
+        }
     }
 
+    /// Buffers tags that were handed to a connection which ended before it could send them
+    ///
+    /// The sender should be cleared first, so that no more tags are handed to the connection.
+    pub fn requeue_tcp(&mut self, tags: impl IntoIterator<Item = TcpTag>) {
+        for tag in tags {
+            match tag {
+                TcpTag::GameData(_) | TcpTag::MatchInfo(_) | TcpTag::JoystickDescriptor(_) => {}
+                tag => self.buffer_tcp(tag),
+            }
+        }
+    }
+
+    fn buffer_tcp(&mut self, tag: TcpTag) {
+        if self.buffered_tcp.len() == MAX_BUFFERED_TCP {
+            self.buffered_tcp.pop_front();
+        }
+        self.buffered_tcp.push_back(tag);
+    }
+
+    /// Returns the tags that are waiting for TCP to connect
+    pub fn buffered_tcp(&self) -> &VecDeque<TcpTag> {
+        &self.buffered_tcp
+    }
+
+    /// Sets the channel used to send tags to the roboRIO
+    ///
+    /// When a channel is set, the remembered session state is sent through it, followed by any buffered tags.
     pub fn set_tcp_tx(&mut self, tx: Option<UnboundedSender<TcpTag>>) {
         self.pending_tcp = tx;
+
+        if let Some(ref tx) = self.pending_tcp {
+            // Robot code sends its versions again when it connects
+            self.versions.clear();
+
+            let session = self
+                .game_data
+                .iter()
+                .cloned()
+                .map(TcpTag::GameData)
+                .chain(self.match_info.iter().cloned().map(TcpTag::MatchInfo))
+                .chain(
+                    self.descriptors
+                        .iter()
+                        .cloned()
+                        .map(TcpTag::JoystickDescriptor),
+                );
+
+            for tag in session.chain(self.buffered_tcp.drain(..)) {
+                let _ = tx.unbounded_send(tag);
+            }
+        }
+    }
+
+    pub fn connection_state(&self) -> &TcpConnectionState {
+        &self.connection_state
+    }
+
+    pub fn set_connection_state(&mut self, state: TcpConnectionState) {
+        self.connection_state = state;
+    }
+
+    pub fn set_joystick_descriptor_supplier(
+        &mut self,
+        supplier: impl Fn() -> Vec<JoystickDescriptor> + Send + Sync + 'static,
+    ) {
+        self.descriptor_supplier = Some(Box::new(supplier));
+    }
+
+    /// Returns the descriptors of the joysticks currently plugged in
+    ///
+    /// Descriptors from the supplier have their indices set to their position in the list. Any port past those that was
+    /// sent in the last control packet is described by the descriptor queued for it, or by a default descriptor with the
+    /// counts it was sent with.
+    pub fn joystick_descriptors(&self, ports: &[JoystickCounts]) -> Vec<JoystickDescriptor> {
+        let mut descriptors = match self.descriptor_supplier {
+            Some(ref supplier) => supplier(),
+            None => Vec::new(),
+        };
+
+        for (index, descriptor) in descriptors.iter_mut().enumerate() {
+            descriptor.index = index as u8;
+        }
+
+        for (index, counts) in ports.iter().enumerate().skip(descriptors.len()) {
+            let index = index as u8;
+            let descriptor = match self.descriptors.iter().find(|d| d.index == index) {
+                Some(queued) => queued.clone(),
+                None => JoystickDescriptor {
+                    index,
+                    axis_types: vec![0; counts.axes],
+                    button_count: counts.buttons as u8,
+                    pov_count: counts.povs as u8,
+                    ..JoystickDescriptor::default()
+                },
+            };
+            descriptors.push(descriptor);
+        }
+        descriptors
+    }
+
+    pub fn versions(&self) -> &Vec<VersionInfo> {
+        &self.versions
+    }
+
+    /// Records the given version, replacing any previous version reported for the same device
+    pub fn update_version(&mut self, info: VersionInfo) {
+        match self
+            .versions
+            .iter_mut()
+            .find(|v| v.device_type == info.device_type && v.id == info.id && v.name == info.name)
+        {
+            Some(existing) => *existing = info,
+            None => self.versions.push(info),
+        }
+    }
+
+    pub fn disable_faults(&self) -> DisableFaults {
+        self.disable_faults
+    }
+
+    /// Adds the increase in the reported disable fault counters to the running totals
+    pub fn update_disable_faults(&mut self, reported: DisableFaults) {
+        let last = self.last_disable_faults;
+        let totals = &mut self.disable_faults;
+        totals.comms = totals
+            .comms
+            .saturating_add(increase(last.map(|f| f.comms), reported.comms));
+        totals.v12 = totals
+            .v12
+            .saturating_add(increase(last.map(|f| f.v12), reported.v12));
+
+        self.last_disable_faults = Some(reported);
+    }
+
+    pub fn rail_faults(&self) -> RailFaults {
+        self.rail_faults
+    }
+
+    /// Adds the increase in the reported power rail fault counters to the running totals
+    pub fn update_rail_faults(&mut self, reported: RailFaults) {
+        let last = self.last_rail_faults;
+        let totals = &mut self.rail_faults;
+        totals.v6 = totals
+            .v6
+            .saturating_add(increase(last.map(|f| f.v6), reported.v6));
+        totals.v5 = totals
+            .v5
+            .saturating_add(increase(last.map(|f| f.v5), reported.v5));
+        totals.v3_3 = totals
+            .v3_3
+            .saturating_add(increase(last.map(|f| f.v3_3), reported.v3_3));
+
+        self.last_rail_faults = Some(reported);
     }
 
     pub fn set_tcp_consumer(&mut self, consumer: impl FnMut(TcpPacket) + Send + Sync + 'static) {
@@ -55,11 +306,30 @@ impl TcpState {
     }
 }
 
+/// Returns how much a fault counter increased by since it was last reported
+///
+/// The roboRIO counts faults since it booted, so a counter that went down means that it rebooted and started again from 0.
+fn increase(last: Option<u16>, reported: u16) -> u16 {
+    match last {
+        Some(last) if reported >= last => reported - last,
+        _ => reported,
+    }
+}
+
 impl RecvState {
     pub fn new() -> RecvState {
         RecvState {
             battery_voltage: 0f32,
             trace: Trace::empty(),
+            status: None,
+            pdp_log: None,
+            cpu_info: None,
+            ram_info: None,
+            disk_info: None,
+            can_metrics: None,
+            joystick_outputs: Vec::new(),
+            unknown_tags: Vec::new(),
+            udp_error: None,
         }
     }
 
@@ -78,4 +348,253 @@ impl RecvState {
     pub fn set_trace(&mut self, trace: Trace) {
         self.trace = trace;
     }
+
+    pub fn status(&self) -> Option<Status> {
+        self.status
+    }
+
+    pub fn set_status(&mut self, status: Status) {
+        self.status = Some(status);
+    }
+
+    pub fn pdp_log(&self) -> Option<&PdpLog> {
+        self.pdp_log.as_ref()
+    }
+
+    pub fn set_pdp_log(&mut self, pdp_log: PdpLog) {
+        self.pdp_log = Some(pdp_log);
+    }
+
+    pub fn cpu_info(&self) -> Option<&CpuInfo> {
+        self.cpu_info.as_ref()
+    }
+
+    pub fn set_cpu_info(&mut self, cpu_info: CpuInfo) {
+        self.cpu_info = Some(cpu_info);
+    }
+
+    pub fn ram_info(&self) -> Option<&RamInfo> {
+        self.ram_info.as_ref()
+    }
+
+    pub fn set_ram_info(&mut self, ram_info: RamInfo) {
+        self.ram_info = Some(ram_info);
+    }
+
+    pub fn disk_info(&self) -> Option<&DiskInfo> {
+        self.disk_info.as_ref()
+    }
+
+    pub fn set_disk_info(&mut self, disk_info: DiskInfo) {
+        self.disk_info = Some(disk_info);
+    }
+
+    pub fn can_metrics(&self) -> Option<&CanMetrics> {
+        self.can_metrics.as_ref()
+    }
+
+    pub fn set_can_metrics(&mut self, can_metrics: CanMetrics) {
+        self.can_metrics = Some(can_metrics);
+    }
+
+    pub fn joystick_outputs(&self) -> &Vec<JoystickOutput> {
+        &self.joystick_outputs
+    }
+
+    pub fn set_joystick_outputs(&mut self, joystick_outputs: Vec<JoystickOutput>) {
+        self.joystick_outputs = joystick_outputs;
+    }
+
+    pub fn unknown_tags(&self) -> &Vec<UnknownTag> {
+        &self.unknown_tags
+    }
+
+    /// Records the given tag, replacing the last tag received with the same id
+    pub fn update_unknown_tag(&mut self, tag: UnknownTag) {
+        match self.unknown_tags.binary_search_by_key(&tag.id, |t| t.id) {
+            Ok(idx) => self.unknown_tags[idx] = tag,
+            Err(idx) => self.unknown_tags.insert(idx, tag),
+        }
+    }
+
+    pub fn udp_error(&self) -> Option<&str> {
+        self.udp_error.as_deref()
+    }
+
+    pub fn set_udp_error(&mut self, error: String) {
+        self.udp_error = Some(error);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DeviceType;
+    use futures_channel::mpsc::unbounded;
+
+    #[test]
+    fn replay_tcp_on_connect() {
+        let mut state = TcpState::new();
+        let gsm = |gsm: &str| {
+            TcpTag::GameData(GameData {
+                gsm: gsm.to_string(),
+            })
+        };
+
+        state.queue_tcp(gsm("LRL"));
+        state.queue_tcp(gsm("RLR"));
+        for id in 0..MAX_BUFFERED_TCP as u8 + 1 {
+            state.queue_tcp(TcpTag::Raw {
+                id,
+                payload: vec![],
+            });
+        }
+        assert_eq!(state.buffered_tcp().len(), MAX_BUFFERED_TCP);
+
+        let (tx, mut rx) = unbounded();
+        state.set_tcp_tx(Some(tx));
+        assert!(state.buffered_tcp().is_empty());
+
+        match rx.try_recv() {
+            Ok(TcpTag::GameData(gd)) => assert_eq!(gd.gsm, "RLR"),
+            tag => panic!("Expected game data, got {:?}", tag),
+        }
+        // The oldest buffered tag was dropped
+        match rx.try_recv() {
+            Ok(TcpTag::Raw { id, .. }) => assert_eq!(id, 1),
+            tag => panic!("Expected a raw tag, got {:?}", tag),
+        }
+
+        // Tags are buffered again once the connection drops
+        drop(rx);
+        state.queue_tcp(gsm("LLL"));
+        state.queue_tcp(TcpTag::Raw {
+            id: 0,
+            payload: vec![],
+        });
+        assert_eq!(state.buffered_tcp().len(), 1);
+    }
+
+    #[test]
+    fn requeue_unsent_tcp() {
+        let mut state = TcpState::new();
+        let (tx, mut rx) = unbounded();
+        state.set_tcp_tx(Some(tx));
+
+        state.queue_tcp(TcpTag::GameData(GameData {
+            gsm: "LRL".to_string(),
+        }));
+        state.queue_tcp(TcpTag::Raw {
+            id: 1,
+            payload: vec![],
+        });
+
+        // The connection ends without sending either tag
+        state.set_tcp_tx(None);
+        rx.close();
+        state.requeue_tcp(std::iter::from_fn(|| rx.try_recv().ok()));
+
+        // Game data is sent again on connect anyway, so only the raw tag is buffered
+        assert_eq!(state.buffered_tcp().len(), 1);
+        match state.buffered_tcp().front() {
+            Some(TcpTag::Raw { id, .. }) => assert_eq!(*id, 1),
+            tag => panic!("Expected a raw tag, got {:?}", tag),
+        }
+    }
+
+    #[test]
+    fn clear_versions_on_connect() {
+        let version = |name: &str, version: &str| VersionInfo {
+            device_type: DeviceType::Software,
+            id: 0,
+            name: name.to_string(),
+            version: version.to_string(),
+        };
+
+        let mut state = TcpState::new();
+        let (tx, _rx) = unbounded();
+        state.set_tcp_tx(Some(tx));
+        state.update_version(version("roboRIO Image", "FRC_roboRIO_2020_v10"));
+        state.update_version(version("WPILib", "2020.3.2"));
+        state.update_version(version("WPILib", "2021.1.2"));
+        assert_eq!(state.versions().len(), 2);
+        assert_eq!(state.versions()[1].version, "2021.1.2");
+
+        // Versions are kept while disconnected, but replaced by whatever the robot reports after reconnecting
+        state.set_tcp_tx(None);
+        assert_eq!(state.versions().len(), 2);
+        let (tx, _rx) = unbounded();
+        state.set_tcp_tx(Some(tx));
+        assert!(state.versions().is_empty());
+    }
+
+    #[test]
+    fn total_faults_across_reboots() {
+        let mut state = TcpState::new();
+        let disable = |comms, v12| DisableFaults { comms, v12 };
+
+        state.update_disable_faults(disable(2, 0));
+        state.update_disable_faults(disable(2, 1));
+        state.update_disable_faults(disable(5, 1));
+        assert_eq!(state.disable_faults(), disable(5, 1));
+
+        // The roboRIO rebooted and counts from 0 again
+        state.update_disable_faults(disable(1, 0));
+        assert_eq!(state.disable_faults(), disable(6, 1));
+        state.update_disable_faults(disable(3, 2));
+        assert_eq!(state.disable_faults(), disable(8, 3));
+
+        let rail = |v6, v5, v3_3| RailFaults { v6, v5, v3_3 };
+        state.update_rail_faults(rail(1, 0, 4));
+        state.update_rail_faults(rail(2, 1, 4));
+        assert_eq!(state.rail_faults(), rail(2, 1, 4));
+        state.update_rail_faults(rail(0, 1, 1));
+        assert_eq!(state.rail_faults(), rail(2, 1, 5));
+    }
+
+    #[test]
+    fn default_joystick_descriptors() {
+        let mut state = TcpState::new();
+        let gamepad = JoystickCounts::default();
+        let ports = [
+            gamepad,
+            gamepad,
+            JoystickCounts {
+                axes: 2,
+                buttons: 12,
+                povs: 0,
+            },
+        ];
+
+        // Every port gets a descriptor matching the counts it's sent with
+        let descriptors = state.joystick_descriptors(&ports);
+        assert_eq!(descriptors.len(), 3);
+        for (index, descriptor) in descriptors.iter().enumerate() {
+            assert_eq!(descriptor.index, index as u8);
+            assert_eq!(JoystickCounts::from(descriptor), ports[index]);
+        }
+
+        // Queued descriptors replace the default for their port, and the supplier's replace both
+        state.queue_tcp(TcpTag::JoystickDescriptor(JoystickDescriptor {
+            index: 2,
+            name: "Flight stick".to_string(),
+            ..JoystickDescriptor::default()
+        }));
+        state.set_joystick_descriptor_supplier(|| {
+            vec![JoystickDescriptor {
+                index: 4,
+                name: "Xbox controller".to_string(),
+                is_xbox: true,
+                ..JoystickDescriptor::default()
+            }]
+        });
+
+        let descriptors = state.joystick_descriptors(&ports);
+        let names = descriptors.iter().map(|d| &d.name[..]).collect::<Vec<_>>();
+        assert_eq!(names, ["Xbox controller", "", "Flight stick"]);
+        assert_eq!(descriptors[0].index, 0);
+        assert_eq!(JoystickCounts::from(&descriptors[1]), gamepad);
+
+        assert!(TcpState::new().joystick_descriptors(&[]).is_empty());
+    }
 }
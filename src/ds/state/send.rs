@@ -2,9 +2,42 @@ use crate::ds::state::{DsMode, JoystickSupplier};
 use crate::proto::udp::outbound::types::tags::*;
 use crate::proto::udp::outbound::types::{Control, Request};
 use crate::proto::udp::outbound::*;
-use crate::{Alliance, JoystickValue, Mode};
+use crate::util::host_timezone;
+use crate::{Alliance, JoystickDescriptor, JoystickValue, Mode};
+use bytes::BytesMut;
 use std::f32;
 
+/// The number of axes, buttons and POVs sent for a joystick port
+///
+/// Ports start out with the layout of a typical gamepad, and take on the counts of their descriptor once one is sent to
+/// the roboRIO, so that robot code sees the same layout in every packet.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct JoystickCounts {
+    pub axes: usize,
+    pub buttons: usize,
+    pub povs: usize,
+}
+
+impl Default for JoystickCounts {
+    fn default() -> Self {
+        JoystickCounts {
+            axes: 6,
+            buttons: 10,
+            povs: 1,
+        }
+    }
+}
+
+impl From<&JoystickDescriptor> for JoystickCounts {
+    fn from(descriptor: &JoystickDescriptor) -> Self {
+        JoystickCounts {
+            axes: descriptor.axis_types.len(),
+            buttons: descriptor.button_count as usize,
+            povs: descriptor.pov_count as usize,
+        }
+    }
+}
+
 /// State containing all the data relevant to constructing a UDP control packet to the roboRIO
 pub struct SendState {
     /// The mode the robot should be enabled in
@@ -21,9 +54,15 @@ pub struct SendState {
     pending_udp: Vec<UdpTag>,
     /// An optional source for joystick values that will be encoded and sent with the packet
     joystick_provider: Option<Box<JoystickSupplier>>,
+    /// The counts that each joystick port is sent with, grown whenever the supplier provides values past them
+    joystick_counts: Vec<JoystickCounts>,
+    /// The number of joysticks the supplier provided for the last packet
+    joystick_ports: usize,
     /// Pending reboot or code restart requests
     pending_request: Option<Request>,
     dsmode: DsMode,
+    /// The IANA timezone sent to the roboRIO alongside the date, detected from the host unless overridden
+    timezone: Option<String>,
 }
 
 impl SendState {
@@ -36,8 +75,11 @@ impl SendState {
             alliance,
             pending_udp: Vec::new(),
             joystick_provider: None,
+            joystick_counts: Vec::new(),
+            joystick_ports: 0,
             pending_request: None,
             dsmode: DsMode::Normal,
+            timezone: host_timezone(),
         }
     }
 
@@ -60,55 +102,73 @@ impl SendState {
         self.joystick_provider = Some(Box::new(supplier))
     }
 
+    /// Returns the counts of each joystick port that was sent in the last packet
+    pub fn joystick_counts(&self) -> &[JoystickCounts] {
+        &self.joystick_counts[..self.joystick_ports]
+    }
+
+    /// Sends each joystick with the counts from the descriptor for its port
+    pub fn set_joystick_counts(&mut self, descriptors: &[JoystickDescriptor]) {
+        for descriptor in descriptors {
+            let index = descriptor.index as usize;
+            if self.joystick_counts.len() <= index {
+                self.joystick_counts
+                    .resize(index + 1, JoystickCounts::default());
+            }
+            self.joystick_counts[index] = JoystickCounts::from(descriptor);
+        }
+    }
+
     pub fn set_alliance(&mut self, alliance: Alliance) {
         self.alliance = alliance;
     }
 
-    /// Constructs a control packet from the current state
+    /// Encodes a control packet from the current state to the end of `buf`
     ///
     /// if [self.joystick_provider] is Some, it will be used to construct the joysticks tag
     /// if [self.request] is Some, its value will be consumed and sent to the roboRIO
-    pub fn control(&mut self) -> UdpControlPacket {
+    ///
+    /// Once the queue of pending tags has grown to fit them, no allocations are made by encoding.
+    pub fn encode_control(&mut self, buf: &mut BytesMut) {
         if let Some(ref supplier) = &self.joystick_provider {
             let joysticks = supplier();
 
+            self.joystick_ports = joysticks.len();
+            if self.joystick_counts.len() < joysticks.len() {
+                self.joystick_counts
+                    .resize(joysticks.len(), JoystickCounts::default());
+            }
+
             // Joystick tags come one after another, iterate over the outer Vec and queue with each loop
-            for joystick in &joysticks {
-                let mut axes = vec![0; 6];
-                let mut buttons = vec![false; 10];
-                let mut povs = vec![-1i16];
+            for (joystick, counts) in joysticks.iter().zip(self.joystick_counts.iter_mut()) {
+                let mut tag = Joysticks::with_counts(counts.axes, counts.buttons, counts.povs);
 
+                // Values past what the roboRIO accepts are dropped, anything else grows the joystick to fit it
                 for value in joystick {
-                    // If statements bound check to stop it from crashing
-                    match value {
+                    match *value {
                         JoystickValue::Button { id, pressed } => {
-                            if *id >= 1 && *id <= 10 {
-                                let id = id - 1;
-                                buttons.remove(id as usize);
-                                buttons.insert(id as usize, *pressed)
-                            }
+                            tag.set_button(id as usize, pressed)
                         }
                         JoystickValue::Axis { id, value } => {
-                            if *id <= 5 {
-                                let value = if (*value - 1.0).abs() < f32::EPSILON {
-                                    127i8
-                                } else {
-                                    (value * 128f32) as i8
-                                };
-
-                                axes.remove(*id as usize);
-                                axes.insert(*id as usize, value);
-                            }
-                        }
-                        JoystickValue::POV { id, angle } => {
-                            if *id == 0 {
-                                povs.remove(*id as usize);
-                                povs.insert(*id as usize, *angle);
-                            }
+                            let value = if (value - 1.0).abs() < f32::EPSILON {
+                                127i8
+                            } else {
+                                (value * 128f32) as i8
+                            };
+                            tag.set_axis(id as usize, value);
                         }
+                        JoystickValue::POV { id, angle } => tag.set_pov(id as usize, angle),
                     }
                 }
-                self.queue_udp(UdpTag::Joysticks(Joysticks::new(axes, buttons, povs)));
+
+                // Later packets keep any growth, so that the counts don't change from packet to packet
+                let (axes, buttons, povs) = tag.counts();
+                *counts = JoystickCounts {
+                    axes,
+                    buttons,
+                    povs,
+                };
+                self.pending_udp.push(UdpTag::Joysticks(tag));
             }
         }
 
@@ -122,26 +182,16 @@ impl SendState {
             control |= Control::ESTOP
         }
 
-        let mut tags: Vec<Box<dyn Tag>> = Vec::new();
-
-        for tag in self.pending_udp.clone() {
-            match tag {
-                UdpTag::Timezone(tz) => tags.push(Box::new(tz)),
-                UdpTag::DateTime(dt) => tags.push(Box::new(dt)),
-                UdpTag::Joysticks(joy) => tags.push(Box::new(joy)),
-                UdpTag::Countdown(cnt) => tags.push(Box::new(cnt)),
-            }
-        }
-
-        self.pending_udp.clear();
-
         UdpControlPacket {
             seqnum: self.udp_seqnum,
             control,
             request: self.pending_request.take(),
             alliance: self.alliance,
-            tags,
+            tags: &self.pending_udp,
         }
+        .encode(buf);
+
+        self.pending_udp.clear();
     }
 
     pub fn mode(&self) -> &Mode {
@@ -152,6 +202,19 @@ impl SendState {
         self.mode = mode;
     }
 
+    pub fn timezone(&self) -> Option<&str> {
+        self.timezone.as_deref()
+    }
+
+    pub fn set_timezone(&mut self, timezone: Option<&str>) {
+        self.timezone = timezone.map(str::to_string);
+    }
+
+    /// Goes back to sending the timezone detected from the host
+    pub fn reset_timezone(&mut self) {
+        self.timezone = host_timezone();
+    }
+
     pub fn ds_mode(&self) -> &DsMode {
         &self.dsmode
     }
@@ -168,7 +231,6 @@ impl SendState {
         self.udp_seqnum = 0;
     }
 
-    #[allow(unused)]
     pub fn seqnum(&self) -> u16 {
         self.udp_seqnum
     }
@@ -194,3 +256,67 @@ impl SendState {
         self.estopped
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_stable_joystick_counts() {
+        let mut state = SendState::new(Alliance::new_red(1));
+        state.set_joystick_supplier(|| {
+            vec![
+                // A joystick that was attached without any values yet
+                vec![],
+                vec![JoystickValue::Button {
+                    id: 3,
+                    pressed: true,
+                }],
+            ]
+        });
+
+        let mut buf = BytesMut::new();
+        state.encode_control(&mut buf);
+
+        // Both joysticks are sent with 6 axes, 10 buttons and 1 POV
+        let joystick = |buttons: u8| {
+            [
+                0x0e, 0x0c, 6, 0, 0, 0, 0, 0, 0, 10, 0, buttons, 1, 0xff, 0xff,
+            ]
+        };
+        assert_eq!(&buf[6..], &[joystick(0), joystick(0b0100)].concat()[..]);
+    }
+
+    #[test]
+    fn encode_joystick_counts_from_descriptor() {
+        let mut state = SendState::new(Alliance::new_red(1));
+        state.set_joystick_counts(&[JoystickDescriptor {
+            index: 0,
+            axis_types: vec![0; 2],
+            button_count: 4,
+            pov_count: 0,
+            ..JoystickDescriptor::default()
+        }]);
+        state.set_joystick_supplier(|| vec![vec![]]);
+
+        let mut buf = BytesMut::new();
+        state.encode_control(&mut buf);
+        assert_eq!(&buf[6..], &[0x07, 0x0c, 2, 0, 0, 4, 0, 0][..]);
+
+        // A value past the counts grows them, for this packet and the ones after it
+        state.set_joystick_supplier(|| {
+            vec![vec![JoystickValue::Button {
+                id: 5,
+                pressed: true,
+            }]]
+        });
+        buf.clear();
+        state.encode_control(&mut buf);
+        assert_eq!(&buf[6..], &[0x07, 0x0c, 2, 0, 0, 5, 0b1_0000, 0][..]);
+
+        state.set_joystick_supplier(|| vec![vec![]]);
+        buf.clear();
+        state.encode_control(&mut buf);
+        assert_eq!(&buf[6..], &[0x07, 0x0c, 2, 0, 0, 5, 0, 0][..]);
+    }
+}
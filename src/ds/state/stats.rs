@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How long a packet can go without a response before it is considered lost
+const LOST_TIMEOUT: Duration = Duration::from_secs(1);
+/// Responses with trip times longer than this are counted as late
+const LATE_THRESHOLD: Duration = Duration::from_millis(100);
+/// The number of packets that the rolling statistics are computed over, 5 seconds worth at 50Hz
+const WINDOW: usize = 250;
+
+/// A snapshot of the health of the UDP connection to the roboRIO
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct ConnectionStats {
+    /// The round trip time of the most recently answered packet
+    pub trip_time: Option<Duration>,
+    /// The average round trip time over the most recently answered packets
+    pub average_trip_time: Option<Duration>,
+    /// The number of control packets sent to the roboRIO
+    pub sent_packets: u32,
+    /// The number of control packets that the roboRIO has responded to
+    pub received_packets: u32,
+    /// The number of control packets that weren't responded to within a second
+    pub lost_packets: u32,
+    /// The number of responses that took longer than 100ms, or that arrived after the packet was considered lost
+    pub late_packets: u32,
+    /// The number of responses that were discarded because a newer response had already been received
+    pub stale_packets: u32,
+    /// The percentage of recent packets that were lost, ranging from `0.0..=100.0`
+    pub packet_loss: f32,
+}
+
+/// Matches responses from the roboRIO with the control packets that they echo, to measure latency and packet loss
+pub struct PacketTracker {
+    /// Sequence numbers and send times of packets that haven't been responded to, oldest first
+    in_flight: VecDeque<(u16, Instant)>,
+    /// Whether each of the most recently resolved packets was lost
+    history: VecDeque<bool>,
+    /// Trip times of the most recently answered packets
+    trip_times: VecDeque<Duration>,
+    /// The sequence number of the newest response that has been received
+    last_received: Option<u16>,
+    stats: ConnectionStats,
+}
+
+impl PacketTracker {
+    pub fn new() -> PacketTracker {
+        PacketTracker {
+            in_flight: VecDeque::new(),
+            history: VecDeque::new(),
+            trip_times: VecDeque::new(),
+            last_received: None,
+            stats: ConnectionStats::default(),
+        }
+    }
+
+    /// Clears all tracked packets and statistics, used when the sequence number is reset
+    pub fn reset(&mut self) {
+        *self = PacketTracker::new();
+    }
+
+    /// Forgets the newest received sequence number, used when the connection to the roboRIO times out
+    ///
+    /// Sequence numbers keep advancing while the roboRIO is unreachable, so after a long enough outage
+    /// fresh responses would otherwise compare as older than the last one received before it.
+    pub fn reset_sequence(&mut self) {
+        self.last_received = None;
+    }
+
+    /// Records that the control packet with the given sequence number was sent at `now`
+    pub fn sent(&mut self, seqnum: u16, now: Instant) {
+        self.expire(now);
+        self.in_flight.push_back((seqnum, now));
+        self.stats.sent_packets = self.stats.sent_packets.wrapping_add(1);
+    }
+
+    /// Records that a response echoing the given sequence number was received at `now`
+    ///
+    /// Returns false if the response is stale, meaning that a response to a newer packet has already been received.
+    /// Stale responses still count towards latency statistics, but their contents should be discarded.
+    pub fn received(&mut self, seqnum: u16, now: Instant) -> bool {
+        self.expire(now);
+        self.stats.received_packets = self.stats.received_packets.wrapping_add(1);
+
+        match self.in_flight.iter().position(|(seq, _)| *seq == seqnum) {
+            Some(idx) => {
+                let (_, sent) = self.in_flight.remove(idx).unwrap();
+                let trip_time = now.duration_since(sent);
+                if trip_time > LATE_THRESHOLD {
+                    self.stats.late_packets += 1;
+                }
+
+                push_bounded(&mut self.trip_times, trip_time);
+                push_bounded(&mut self.history, false);
+                self.stats.trip_time = Some(trip_time);
+            }
+            // The packet was already counted as lost
+            None => self.stats.late_packets += 1,
+        }
+
+        match self.last_received {
+            Some(last) if !is_newer(seqnum, last) => {
+                self.stats.stale_packets += 1;
+                false
+            }
+            _ => {
+                self.last_received = Some(seqnum);
+                true
+            }
+        }
+    }
+
+    /// Returns a snapshot of the current statistics
+    pub fn stats(&self) -> ConnectionStats {
+        let mut stats = self.stats;
+
+        if !self.trip_times.is_empty() {
+            let total: Duration = self.trip_times.iter().sum();
+            stats.average_trip_time = Some(total / self.trip_times.len() as u32);
+        }
+
+        if !self.history.is_empty() {
+            let lost = self.history.iter().filter(|lost| **lost).count();
+            stats.packet_loss = lost as f32 / self.history.len() as f32 * 100.0;
+        }
+
+        stats
+    }
+
+    /// Marks all packets that have been in flight for longer than `LOST_TIMEOUT` as lost
+    fn expire(&mut self, now: Instant) {
+        while let Some((_, sent)) = self.in_flight.front() {
+            if now.duration_since(*sent) < LOST_TIMEOUT {
+                break;
+            }
+
+            self.in_flight.pop_front();
+            self.stats.lost_packets += 1;
+            push_bounded(&mut self.history, true);
+        }
+    }
+}
+
+/// Returns whether sequence number `a` comes after `b`, accounting for wraparound
+///
+/// `a` is considered newer if it is less than half of the sequence number space ahead of `b`.
+fn is_newer(a: u16, b: u16) -> bool {
+    let diff = a.wrapping_sub(b);
+    diff != 0 && diff < 0x8000
+}
+
+fn push_bounded<T>(queue: &mut VecDeque<T>, value: T) {
+    if queue.len() == WINDOW {
+        queue.pop_front();
+    }
+    queue.push_back(value);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tracks_trip_time_and_loss() {
+        let start = Instant::now();
+        let mut tracker = PacketTracker::new();
+
+        tracker.sent(0, start);
+        tracker.sent(1, start + Duration::from_millis(20));
+        tracker.received(0, start + Duration::from_millis(5));
+
+        let stats = tracker.stats();
+        assert_eq!(stats.trip_time, Some(Duration::from_millis(5)));
+        assert_eq!(stats.packet_loss, 0.0);
+
+        // Packet 1 is never answered, and expires when the next packet is sent
+        tracker.sent(2, start + Duration::from_millis(1500));
+        tracker.received(2, start + Duration::from_millis(1650));
+        let stats = tracker.stats();
+        assert_eq!(stats.sent_packets, 3);
+        assert_eq!(stats.received_packets, 2);
+        assert_eq!(stats.lost_packets, 1);
+        assert_eq!(stats.late_packets, 1);
+        assert_eq!(stats.average_trip_time, Some(Duration::from_micros(77_500)));
+        assert!((stats.packet_loss - 100.0 / 3.0).abs() < 0.001);
+
+        // A response to a packet that was already counted as lost is late
+        tracker.received(1, start + Duration::from_millis(1700));
+        assert_eq!(tracker.stats().late_packets, 2);
+    }
+
+    #[test]
+    fn rejects_stale_packets() {
+        let now = Instant::now();
+        let mut tracker = PacketTracker::new();
+
+        assert!(tracker.received(0xfffe, now));
+        assert!(tracker.received(0x0001, now));
+        assert!(!tracker.received(0xffff, now));
+        assert!(!tracker.received(0x0001, now));
+        assert!(tracker.received(0x0002, now));
+        assert_eq!(tracker.stats().stale_packets, 2);
+
+        tracker.reset_sequence();
+        assert!(tracker.received(0x9000, now));
+    }
+}
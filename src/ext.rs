@@ -31,33 +31,6 @@ pub trait BufExt: Buf {
         }
     }
 
-    /// Reads an unsigned big endian long from `self`
-    fn read_u64_be(&mut self) -> Result<u64> {
-        if self.remaining() >= 8 {
-            Ok(self.get_u64())
-        } else {
-            Err(Error::new(ErrorKind::UnexpectedEof, "self.remaining() < 8"))
-        }
-    }
-
-    /// Reads a signed byte from `self`
-    fn read_i8(&mut self) -> Result<i8> {
-        if self.remaining() >= 1 {
-            Ok(self.get_i8())
-        } else {
-            Err(Error::new(ErrorKind::UnexpectedEof, "self.remaining() < 1"))
-        }
-    }
-
-    /// Reads a signed big endian short from `self`
-    fn read_i16_be(&mut self) -> Result<i16> {
-        if self.remaining() >= 2 {
-            Ok(self.get_i16())
-        } else {
-            Err(Error::new(ErrorKind::UnexpectedEof, "self.remaining() < 2"))
-        }
-    }
-
     /// Reads a signed big endian integer from `self`
     fn read_i32_be(&mut self) -> Result<i32> {
         if self.remaining() >= 4 {
@@ -67,15 +40,6 @@ pub trait BufExt: Buf {
         }
     }
 
-    /// Reads a signed big endian long from `self`
-    fn read_i64_be(&mut self) -> Result<i64> {
-        if self.remaining() >= 8 {
-            Ok(self.get_i64())
-        } else {
-            Err(Error::new(ErrorKind::UnexpectedEof, "self.remaining() < 8"))
-        }
-    }
-
     fn read_f32_be(&mut self) -> Result<f32> {
         if self.remaining() >= 4 {
             Ok(self.get_f32())
@@ -83,15 +47,6 @@ pub trait BufExt: Buf {
             Err(Error::new(ErrorKind::UnexpectedEof, "self.remaining() < 4"))
         }
     }
-
-    /// Reads a double precision floating point number big endian from `self`
-    fn read_f64_be(&mut self) -> Result<f64> {
-        if self.remaining() >= 8 {
-            Ok(self.get_f64())
-        } else {
-            Err(Error::new(ErrorKind::UnexpectedEof, "self.remaining() < 8"))
-        }
-    }
 }
 
 impl<B: Buf> BufExt for B {}
@@ -0,0 +1,27 @@
+//! Entry points for the cargo-fuzz targets in `fuzz/`. This module is not part of the public API.
+
+use crate::proto::tcp::DsTcpCodec;
+use crate::proto::udp::DsUdpCodec;
+use bytes::BytesMut;
+use tokio_util::codec::Decoder;
+
+/// Decodes `data` as a single UDP status packet from the roboRIO
+pub fn decode_udp(data: &[u8]) {
+    let _ = DsUdpCodec.decode(&mut BytesMut::from(data));
+}
+
+/// Decodes `data` as a stream of TCP frames from the roboRIO, until it runs out of complete frames
+///
+/// Panics if the decoder returns without consuming a frame, as that would stall the TCP connection.
+pub fn decode_tcp(data: &[u8]) {
+    let mut buf = BytesMut::from(data);
+    let mut codec = DsTcpCodec;
+
+    loop {
+        let remaining = buf.len();
+        match codec.decode(&mut buf) {
+            Ok(Some(_)) | Err(_) => assert!(buf.len() < remaining, "TCP decoder made no progress"),
+            Ok(None) => break,
+        }
+    }
+}
@@ -1,30 +1,43 @@
 //! # ds
 //!
 //! `ds` is a library that allows for control of FIRST Robotics Competition robots.
-//! The protocol supported currently is that of the 2018 season, with only the bare minimum
-//! required to control the robot currently consumed. Diagnostic and telemetry information is not decoded and is discarded
+//! The protocol supported currently is that of the 2018 season. Alongside what is required to control the robot,
+//! the telemetry the roboRIO reports (power distribution, CPU, memory, disk and CAN bus usage) is decoded and can be queried
+//! from the [`DriverStation`](struct.DriverStation.html).
 //!
 //! The core trait for use of the crate is the [`DriverStation`](struct.DriverStation.html) crate. This crate
 //! provides an API for connecting and controlling to the roboRIO in an FRC robot. It also allows for users to
 //! provide joystick input using arbitrary APIs, and to consume any incoming TCP packets.
+//!
+//! `DriverStation` runs on a tokio runtime of its own, and blocks the calling thread to access its state. Applications that
+//! already use tokio should use [`AsyncDriverStation`](struct.AsyncDriverStation.html) instead, which runs on the caller's runtime.
 
 #![doc(html_root_url = "https://docs.rs/ds/1.0.1")]
 
 #[macro_use]
 extern crate bitflags;
-#[macro_use]
-extern crate smallvec;
 
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
 mod ds;
 mod ext;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
 mod proto;
 pub(crate) mod util;
 
-pub use self::ds::state::{DsMode, Mode};
-pub use self::ds::{DriverStation, JoystickValue};
+pub use self::ds::state::{ConnectionStats, DsMode, Mode, TcpConnectionState};
+pub use self::ds::{AsyncDriverStation, DriverStation, DriverStationBuilder, JoystickValue};
 pub use self::proto::tcp::inbound::*;
 pub use self::proto::tcp::outbound::*;
+pub use self::proto::udp::inbound::types::tags::{
+    CanMetrics, CpuInfo, CpuUsage, DiskInfo, JoystickOutput, PdpLog, PowerDistributionType,
+    RamInfo, UnknownTag,
+};
 pub use self::proto::udp::inbound::types::Trace;
 pub use self::proto::udp::outbound::types::*;
+pub use self::proto::DecodeError;
 
 pub type Result<T> = std::result::Result<T, failure::Error>;
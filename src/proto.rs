@@ -1,2 +1,36 @@
+use std::error::Error;
+use std::fmt;
+
 pub mod tcp;
 pub mod udp;
+
+/// Errors encountered while decoding packets from the roboRIO
+///
+/// Running out of data partway through a packet is reported as an `std::io::Error` of kind `UnexpectedEof` instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// A TCP frame had a length of 0, so it couldn't contain an id
+    EmptyFrame,
+    /// A frame was too short to contain the fields required by its id
+    TooShort { id: u8, len: usize, min: usize },
+    /// A tag had a length that doesn't match any known layout for its id
+    InvalidLength { id: u8, len: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::EmptyFrame => write!(f, "TCP frame has a length of 0"),
+            DecodeError::TooShort { id, len, min } => write!(
+                f,
+                "Frame with id {:#04x} has {} bytes, at least {} are required",
+                id, len, min
+            ),
+            DecodeError::InvalidLength { id, len } => {
+                write!(f, "Tag with id {:#04x} has unexpected length {}", id, len)
+            }
+        }
+    }
+}
+
+impl Error for DecodeError {}
@@ -1,8 +1,9 @@
 use crate::ext::BufExt;
-use crate::proto::tcp::outbound::{OutgoingTcpTag, TcpTag};
-use crate::{Stdout, TcpPacket};
+use crate::proto::tcp::inbound::IncomingTcpPacket;
+use crate::proto::tcp::outbound::TcpTag;
+use crate::proto::DecodeError;
+use crate::{DisableFaults, ErrorMessage, RailFaults, Stdout, TcpPacket, VersionInfo};
 use bytes::{Buf, BytesMut};
-use std::io;
 use tokio_util::codec::{Decoder, Encoder};
 
 pub mod inbound;
@@ -16,12 +17,7 @@ impl Encoder for DsTcpCodec {
     type Error = failure::Error;
 
     fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        match item {
-            TcpTag::GameData(gd) => {
-                dst.extend(gd.construct().iter());
-            }
-            TcpTag::MatchInfo(mi) => dst.extend(mi.construct().iter()),
-        }
+        dst.extend(item.construct()?.iter());
         Ok(())
     }
 }
@@ -31,42 +27,146 @@ impl Decoder for DsTcpCodec {
     type Error = failure::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let mut buf = src.clone().freeze();
-
-        fn inner(buf: &mut impl Buf) -> crate::Result<(TcpPacket, usize)> {
-            let len = buf.read_u16_be()?;
-
-            let id = buf.read_u8()?;
-            match id {
-                0x0c => Ok((
-                    TcpPacket::Stdout(Stdout::decode(buf, len as usize - 1)?),
-                    len as usize + 2,
-                )),
-                _ => {
-                    for _ in 0..(len - 1) {
-                        let _ = buf.read_u8()?;
-                    }
-                    Ok((TcpPacket::Dummy, len as usize + 2))
-                }
+        if src.len() < 2 {
+            return Ok(None);
+        }
+
+        let len = usize::from(u16::from_be_bytes([src[0], src[1]]));
+        if src.len() < len + 2 {
+            return Ok(None);
+        }
+
+        // The frame is taken out of the buffer before it's decoded, so that a malformed frame is skipped
+        // instead of being decoded again every time the stream is polled.
+        let mut buf = src.split_to(len + 2).freeze();
+        buf.advance(2);
+
+        if len == 0 {
+            return Err(DecodeError::EmptyFrame.into());
+        }
+
+        let id = buf.read_u8()?;
+        let packet = match id {
+            0x04 => TcpPacket::DisableFaults(DisableFaults::decode(&mut buf)?),
+            0x05 => TcpPacket::RailFaults(RailFaults::decode(&mut buf)?),
+            0x0a => TcpPacket::VersionInfo(VersionInfo::decode(&mut buf)?),
+            0x0b => TcpPacket::ErrorMessage(ErrorMessage::decode(&mut buf)?),
+            0x0c => TcpPacket::Stdout(Stdout::decode(&mut buf, len - 1)?),
+            _ => TcpPacket::Unknown {
+                id,
+                payload: buf.to_vec(),
+            },
+        };
+
+        Ok(Some(packet))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DeviceType;
+
+    #[test]
+    fn decode_malformed_frames() {
+        let mut codec = DsTcpCodec;
+        let mut buf = BytesMut::from(
+            &[
+                0x00, 0x00, // Empty frame
+                0x00, 0x02, 0x0c, 0x00, // Stdout frame too short for its header
+                0x00, 0x08, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+                0xff, // Stdout with invalid UTF-8
+                0x00, 0x05, 0x0c, // Incomplete frame
+            ][..],
+        );
+
+        assert!(codec.decode(&mut buf).is_err());
+        assert!(codec.decode(&mut buf).is_err());
+        match codec.decode(&mut buf).unwrap() {
+            Some(TcpPacket::Stdout(stdout)) => assert_eq!(stdout.message, "\u{fffd}"),
+            packet => panic!("Expected Stdout, got {:?}", packet),
+        }
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert_eq!(buf.len(), 3);
+    }
+
+    #[test]
+    fn decode_error_message() {
+        let mut frame = vec![0x00, 0x00, 0x0b];
+        frame.extend_from_slice(&[0x3f, 0x80, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01]);
+        frame.extend_from_slice(&(-1003i32).to_be_bytes());
+        frame.push(0x01);
+        for s in &["CAN frame not received", "CANSparkMax.java:42", ""] {
+            frame.extend_from_slice(&(s.len() as u16).to_be_bytes());
+            frame.extend_from_slice(s.as_bytes());
+        }
+        let len = (frame.len() - 2) as u16;
+        frame[..2].copy_from_slice(&len.to_be_bytes());
+
+        match DsTcpCodec.decode(&mut BytesMut::from(&frame[..])).unwrap() {
+            Some(TcpPacket::ErrorMessage(msg)) => {
+                assert_eq!(msg.timestamp, 1.0);
+                assert_eq!(msg.seqnum, 2);
+                assert_eq!(msg.code, -1003);
+                assert!(msg.is_error);
+                assert_eq!(msg.details, "CAN frame not received");
+                assert_eq!(msg.location, "CANSparkMax.java:42");
+                assert_eq!(msg.call_stack, "");
+            }
+            packet => panic!("Expected ErrorMessage, got {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn decode_version_info() {
+        // The version of the roboRIO image, sent when robot code starts
+        let mut frame = vec![0x00, 0x28, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x0d];
+        frame.extend_from_slice(b"roboRIO Image");
+        frame.push(0x14);
+        frame.extend_from_slice(b"FRC_roboRIO_2020_v10");
+
+        match DsTcpCodec.decode(&mut BytesMut::from(&frame[..])).unwrap() {
+            Some(TcpPacket::VersionInfo(info)) => {
+                assert_eq!(info.device_type, DeviceType::Software);
+                assert_eq!(info.id, 0);
+                assert_eq!(info.name, "roboRIO Image");
+                assert_eq!(info.version, "FRC_roboRIO_2020_v10");
             }
+            packet => panic!("Expected VersionInfo, got {:?}", packet),
         }
 
-        use failure::bail;
-        match inner(&mut buf) {
-            Ok((packet, n)) => {
-                src.advance(n);
-                Ok(Some(packet))
+        // A PDP on CAN id 0, with a version string cut short
+        let frame = [0x00, 0x08, 0x0a, 0x08, 0x00, 0x00, 0x00, 0x00, 0x05, b'1'];
+        assert!(DsTcpCodec.decode(&mut BytesMut::from(&frame[..])).is_err());
+    }
+
+    #[test]
+    fn decode_fault_counters() {
+        let mut buf = BytesMut::from(
+            &[
+                0x00, 0x05, 0x04, 0x00, 0x03, 0x01, 0x02, // Disable faults
+                0x00, 0x07, 0x05, 0x00, 0x01, 0x00, 0x00, 0x02, 0x00, // Rail faults
+                0x00, 0x04, 0x05, 0x00, 0x01, 0x00, // Rail faults missing the 3.3V rail
+            ][..],
+        );
+
+        match DsTcpCodec.decode(&mut buf).unwrap() {
+            Some(TcpPacket::DisableFaults(faults)) => {
+                assert_eq!(faults, DisableFaults { comms: 3, v12: 258 })
             }
-            Err(e) => match e.downcast::<io::Error>() {
-                Ok(e) => {
-                    if e.kind() == io::ErrorKind::UnexpectedEof {
-                        Ok(None)
-                    } else {
-                        Err(e.into())
-                    }
+            packet => panic!("Expected DisableFaults, got {:?}", packet),
+        }
+        match DsTcpCodec.decode(&mut buf).unwrap() {
+            Some(TcpPacket::RailFaults(faults)) => assert_eq!(
+                faults,
+                RailFaults {
+                    v6: 1,
+                    v5: 0,
+                    v3_3: 512,
                 }
-                _ => bail!("Failed to decode TCP packet"),
-            },
+            ),
+            packet => panic!("Expected RailFaults, got {:?}", packet),
         }
+        assert!(DsTcpCodec.decode(&mut buf).is_err());
     }
 }
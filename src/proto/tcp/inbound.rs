@@ -1,15 +1,28 @@
 use crate::ext::BufExt;
+use crate::proto::DecodeError;
 use crate::Result as CResult;
 use bytes::Buf;
 use std::io::{Error, ErrorKind};
-use std::str;
 
 /// Enum containing possible incoming TCP packets from the roboRIO
 #[derive(Debug)]
 pub enum TcpPacket {
     /// Contains a message from the robot code's standard output
     Stdout(Stdout),
-    Dummy,
+    /// Contains an error or warning reported by robot code, such as through `DriverStation.reportError`
+    ErrorMessage(ErrorMessage),
+    /// Contains the version of a piece of software or hardware on the robot
+    VersionInfo(VersionInfo),
+    /// Contains the number of times the robot has been disabled due to faults
+    DisableFaults(DisableFaults),
+    /// Contains the number of faults on the roboRIO's power rails
+    RailFaults(RailFaults),
+    /// A packet with an id that this crate doesn't decode
+    Unknown {
+        id: u8,
+        /// The contents of the packet, excluding its length and id
+        payload: Vec<u8>,
+    },
 }
 
 pub(crate) trait IncomingTcpPacket: Sized {
@@ -27,6 +40,15 @@ pub struct Stdout {
 
 impl Stdout {
     pub fn decode(buf: &mut impl Buf, len: usize) -> CResult<Self> {
+        if len < 6 {
+            return Err(DecodeError::TooShort {
+                id: 0x0c,
+                len,
+                min: 6,
+            }
+            .into());
+        }
+
         let timestamp = buf.read_f32_be()?;
         let seqnum = buf.read_u16_be()?;
         let mut v = vec![0; len - 6];
@@ -34,11 +56,172 @@ impl Stdout {
             return Err(Error::new(ErrorKind::UnexpectedEof, "Not enough data").into());
         }
         buf.copy_to_slice(&mut v[..]);
-        let message = str::from_utf8(&v[..])?;
+        // A message with invalid UTF-8 is still worth showing to the user
+        let message = String::from_utf8_lossy(&v[..]).into_owned();
         Ok(Stdout {
             timestamp,
-            message: message.to_string(),
+            message,
+            seqnum,
+        })
+    }
+}
+
+/// Contains an error or warning reported by robot code or WPILib
+#[derive(Debug)]
+pub struct ErrorMessage {
+    pub timestamp: f32,
+    pub seqnum: u16,
+    /// The error code associated with the message. WPILib uses negative codes for its own errors
+    pub code: i32,
+    /// Whether the message is an error, `false` if it is a warning
+    pub is_error: bool,
+    pub details: String,
+    /// The location the message was reported from
+    pub location: String,
+    pub call_stack: String,
+}
+
+impl ErrorMessage {
+    /// Returns whether the message is a warning rather than an error
+    pub fn is_warning(&self) -> bool {
+        !self.is_error
+    }
+}
+
+impl IncomingTcpPacket for ErrorMessage {
+    fn decode(buf: &mut impl Buf) -> CResult<Self> {
+        let timestamp = buf.read_f32_be()?;
+        let seqnum = buf.read_u16_be()?;
+        let _ = buf.read_u16_be()?;
+        let code = buf.read_i32_be()?;
+        let flags = buf.read_u8()?;
+        let details = read_long_string(buf)?;
+        let location = read_long_string(buf)?;
+        let call_stack = read_long_string(buf)?;
+
+        Ok(ErrorMessage {
+            timestamp,
             seqnum,
+            code,
+            is_error: flags & 0x01 != 0,
+            details,
+            location,
+            call_stack,
+        })
+    }
+}
+
+/// The kind of device that a `VersionInfo` describes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeviceType {
+    /// Software running on the roboRIO, such as the image, WPILib or vendor libraries
+    Software,
+    CanTalon,
+    Pdp,
+    Pcm,
+    Other(u8),
+}
+
+impl From<u8> for DeviceType {
+    fn from(ty: u8) -> Self {
+        match ty {
+            0 => DeviceType::Software,
+            2 => DeviceType::CanTalon,
+            8 => DeviceType::Pdp,
+            9 => DeviceType::Pcm,
+            ty => DeviceType::Other(ty),
+        }
+    }
+}
+
+/// Contains the version of a piece of software or hardware on the robot
+///
+/// The roboRIO sends one of these for each component when robot code starts, for example the roboRIO image, WPILib, and
+/// each vendor library.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionInfo {
+    pub device_type: DeviceType,
+    /// The id of the device, the CAN id for CAN devices
+    pub id: u8,
+    pub name: String,
+    pub version: String,
+}
+
+impl IncomingTcpPacket for VersionInfo {
+    fn decode(buf: &mut impl Buf) -> CResult<Self> {
+        let device_type = DeviceType::from(buf.read_u8()?);
+        let _ = buf.read_u16_be()?;
+        let id = buf.read_u8()?;
+        let name = read_short_string(buf)?;
+        let version = read_short_string(buf)?;
+
+        Ok(VersionInfo {
+            device_type,
+            id,
+            name,
+            version,
         })
     }
 }
+
+/// The number of times the robot has been disabled due to faults
+///
+/// The roboRIO reports counts since it booted.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct DisableFaults {
+    /// Disables caused by a loss of communication with the driver station
+    pub comms: u16,
+    /// Disables caused by the 12V input voltage browning out
+    pub v12: u16,
+}
+
+impl IncomingTcpPacket for DisableFaults {
+    fn decode(buf: &mut impl Buf) -> CResult<Self> {
+        let comms = buf.read_u16_be()?;
+        let v12 = buf.read_u16_be()?;
+
+        Ok(DisableFaults { comms, v12 })
+    }
+}
+
+/// The number of short circuits or overcurrent faults on each of the roboRIO's user power rails
+///
+/// The roboRIO reports counts since it booted.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct RailFaults {
+    pub v6: u16,
+    pub v5: u16,
+    pub v3_3: u16,
+}
+
+impl IncomingTcpPacket for RailFaults {
+    fn decode(buf: &mut impl Buf) -> CResult<Self> {
+        let v6 = buf.read_u16_be()?;
+        let v5 = buf.read_u16_be()?;
+        let v3_3 = buf.read_u16_be()?;
+
+        Ok(RailFaults { v6, v5, v3_3 })
+    }
+}
+
+/// Reads a string prefixed with its length as a big endian short
+fn read_long_string(buf: &mut impl Buf) -> CResult<String> {
+    let len = buf.read_u16_be()? as usize;
+    read_string(buf, len)
+}
+
+/// Reads a string prefixed with its length as a byte
+fn read_short_string(buf: &mut impl Buf) -> CResult<String> {
+    let len = buf.read_u8()? as usize;
+    read_string(buf, len)
+}
+
+fn read_string(buf: &mut impl Buf, len: usize) -> CResult<String> {
+    if buf.remaining() < len {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Not enough data").into());
+    }
+
+    let mut v = vec![0; len];
+    buf.copy_to_slice(&mut v[..]);
+    Ok(String::from_utf8_lossy(&v[..]).into_owned())
+}
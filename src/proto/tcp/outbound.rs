@@ -1,33 +1,125 @@
+use crate::util::truncate;
+use crate::Result;
 use byteorder::{BigEndian, WriteBytesExt};
+use failure::bail;
+use std::fmt::Debug;
 
 #[derive(Debug, Clone)]
 pub enum TcpTag {
     MatchInfo(MatchInfo),
     GameData(GameData),
+    /// Describes a joystick to robot code, which uses it for methods such as `DriverStation.getJoystickName`
+    JoystickDescriptor(JoystickDescriptor),
+    /// A frame with an arbitrary id and payload, for frames that aren't otherwise supported by this crate
+    Raw {
+        id: u8,
+        payload: Vec<u8>,
+    },
+    /// A user defined frame, encoded with its implementation of `OutgoingTcpTag`
+    Custom(Box<dyn OutgoingTcpTag>),
 }
 
-pub(crate) trait OutgoingTcpTag {
+impl TcpTag {
+    /// Encodes the tag into a length prefixed frame
+    ///
+    /// Fails if the id and payload of the tag don't fit in the 16 bit length of a frame.
+    pub(crate) fn construct(&self) -> Result<Vec<u8>> {
+        match self {
+            TcpTag::MatchInfo(mi) => mi.construct(),
+            TcpTag::GameData(gd) => gd.construct(),
+            TcpTag::JoystickDescriptor(jd) => jd.construct(),
+            TcpTag::Raw { id, payload } => frame(*id, payload),
+            TcpTag::Custom(tag) => tag.construct(),
+        }
+    }
+}
+
+/// Represents a frame sent to the roboRIO over TCP
+///
+/// This can be implemented to send frames that aren't built into the crate with `TcpTag::Custom`.
+/// The length prefix is added when the frame is constructed, and should not be included in `data()`.
+pub trait OutgoingTcpTag: Send + Sync + Debug + OutgoingTcpTagClone {
+    /// The id of the frame, written after its length
     fn id(&self) -> u8;
 
+    /// The payload of the frame, written after its id
     fn data(&self) -> Vec<u8>;
 
-    fn construct(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
-        buf.push(self.id());
-        buf.extend(self.data());
+    fn construct(&self) -> Result<Vec<u8>> {
+        frame(self.id(), &self.data())
+    }
+}
 
-        let mut out = Vec::new();
-        out.write_u16::<BigEndian>(buf.len() as u16).unwrap();
-        out.extend(buf);
+/// Allows boxed frames to be cloned. This is implemented for all frames that implement `Clone`
+pub trait OutgoingTcpTagClone {
+    fn clone_box(&self) -> Box<dyn OutgoingTcpTag>;
+}
 
-        out
+impl<T: OutgoingTcpTag + Clone + 'static> OutgoingTcpTagClone for T {
+    fn clone_box(&self) -> Box<dyn OutgoingTcpTag> {
+        Box::new(self.clone())
     }
 }
 
-#[derive(Debug, Clone)]
+impl Clone for Box<dyn OutgoingTcpTag> {
+    fn clone(&self) -> Box<dyn OutgoingTcpTag> {
+        self.clone_box()
+    }
+}
+
+/// Prefixes the id and payload of a frame with their combined length
+fn frame(id: u8, data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() >= usize::from(u16::MAX) {
+        bail!(
+            "Frame with id {:#04x} has a {} byte payload, at most {} bytes fit in a frame",
+            id,
+            data.len(),
+            u16::MAX - 1
+        );
+    }
+
+    let mut out = Vec::with_capacity(data.len() + 3);
+    out.write_u16::<BigEndian>(data.len() as u16 + 1).unwrap();
+    out.push(id);
+    out.extend_from_slice(data);
+
+    Ok(out)
+}
+
+/// Information about the current match, which robot code can query through methods such as `DriverStation.getEventName`
+///
+/// Construct one with [`MatchInfo::builder`](#method.builder).
+#[derive(Debug, Clone, Default)]
 pub struct MatchInfo {
-    competition: String,
+    event_name: String,
     match_type: MatchType,
+    match_number: u16,
+    replay_number: u8,
+}
+
+impl MatchInfo {
+    /// Returns a builder for a `MatchInfo`, with no event name and match type `None`
+    pub fn builder() -> MatchInfoBuilder {
+        MatchInfoBuilder {
+            info: MatchInfo::default(),
+        }
+    }
+
+    pub fn event_name(&self) -> &str {
+        &self.event_name
+    }
+
+    pub fn match_type(&self) -> MatchType {
+        self.match_type
+    }
+
+    pub fn match_number(&self) -> u16 {
+        self.match_number
+    }
+
+    pub fn replay_number(&self) -> u8 {
+        self.replay_number
+    }
 }
 
 impl OutgoingTcpTag for MatchInfo {
@@ -36,15 +128,52 @@ impl OutgoingTcpTag for MatchInfo {
     }
 
     fn data(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
-        buf.push(self.competition.len() as u8);
-        buf.extend_from_slice(self.competition.as_bytes());
+        let event_name = truncate(&self.event_name, 255);
+
+        let mut buf = vec![event_name.len() as u8];
+        buf.extend_from_slice(event_name.as_bytes());
         buf.push(self.match_type as u8);
+        buf.write_u16::<BigEndian>(self.match_number).unwrap();
+        buf.push(self.replay_number);
 
         buf
     }
 }
 
+/// Builder for [`MatchInfo`](struct.MatchInfo.html)
+#[derive(Debug, Clone)]
+pub struct MatchInfoBuilder {
+    info: MatchInfo,
+}
+
+impl MatchInfoBuilder {
+    /// Sets the name of the event, truncated to 255 bytes when sent
+    pub fn event_name(mut self, event_name: &str) -> Self {
+        self.info.event_name = event_name.to_string();
+        self
+    }
+
+    pub fn match_type(mut self, match_type: MatchType) -> Self {
+        self.info.match_type = match_type;
+        self
+    }
+
+    pub fn match_number(mut self, match_number: u16) -> Self {
+        self.info.match_number = match_number;
+        self
+    }
+
+    /// Sets the number of times the match has been replayed, 1 for a match that hasn't been replayed
+    pub fn replay_number(mut self, replay_number: u8) -> Self {
+        self.info.replay_number = replay_number;
+        self
+    }
+
+    pub fn build(self) -> MatchInfo {
+        self.info
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct GameData {
     pub gsm: String,
@@ -64,11 +193,135 @@ impl OutgoingTcpTag for GameData {
 }
 
 #[repr(u8)]
-#[derive(Debug, Copy, Clone)]
-#[allow(unused)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
 pub enum MatchType {
+    #[default]
     None = 0,
     Practice = 1,
     Qualifications = 2,
     Eliminations = 3,
 }
+
+/// The type of a HID device, as reported to robot code by `DriverStation.getJoystickType`
+#[repr(i8)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum HidType {
+    #[default]
+    Unknown = -1,
+    XInputUnknown = 0,
+    XInputGamepad = 1,
+    XInputWheel = 2,
+    XInputArcadeStick = 3,
+    XInputFlightStick = 4,
+    XInputDancePad = 5,
+    XInputGuitar = 6,
+    XInputGuitar2 = 7,
+    XInputDrumKit = 8,
+    XInputGuitar3 = 11,
+    XInputArcadePad = 19,
+    HidJoystick = 20,
+    HidGamepad = 21,
+    HidDriving = 22,
+    HidFlight = 23,
+    Hid1stPerson = 24,
+}
+
+/// Describes the joystick plugged into a port, so that robot code can query its name, type and layout
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JoystickDescriptor {
+    /// The port of the joystick, ranging from `0..=5`
+    pub index: u8,
+    pub is_xbox: bool,
+    pub hid_type: HidType,
+    /// The name of the joystick, truncated to 255 bytes when sent
+    pub name: String,
+    /// The type of each axis on the joystick, there should be one entry for each axis up to a maximum of 12
+    pub axis_types: Vec<u8>,
+    pub button_count: u8,
+    pub pov_count: u8,
+}
+
+impl OutgoingTcpTag for JoystickDescriptor {
+    fn id(&self) -> u8 {
+        0x02
+    }
+
+    fn data(&self) -> Vec<u8> {
+        let name = truncate(&self.name, 255);
+        let axis_types = &self.axis_types[..self.axis_types.len().min(12)];
+
+        let mut buf = vec![
+            self.index,
+            self.is_xbox as u8,
+            self.hid_type as i8 as u8,
+            name.len() as u8,
+        ];
+        buf.extend_from_slice(name.as_bytes());
+        buf.push(axis_types.len() as u8);
+        buf.extend_from_slice(axis_types);
+        buf.push(self.button_count);
+        buf.push(self.pov_count);
+
+        buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_match_info() {
+        let info = MatchInfo::builder()
+            .event_name("ONT")
+            .match_type(MatchType::Qualifications)
+            .match_number(42)
+            .replay_number(1)
+            .build();
+
+        assert_eq!(
+            info.construct().unwrap(),
+            &[0x00, 0x09, 0x07, 3, b'O', b'N', b'T', 2, 0x00, 42, 1]
+        );
+    }
+
+    #[test]
+    fn encode_joystick_descriptor() {
+        let descriptor = JoystickDescriptor {
+            index: 1,
+            is_xbox: true,
+            hid_type: HidType::XInputGamepad,
+            name: "Pad".to_string(),
+            axis_types: vec![0, 1],
+            button_count: 10,
+            pov_count: 1,
+        };
+
+        assert_eq!(
+            descriptor.construct().unwrap(),
+            &[0x00, 0x0d, 0x02, 1, 1, 1, 3, b'P', b'a', b'd', 2, 0, 1, 10, 1]
+        );
+    }
+
+    #[test]
+    fn encode_raw_frame() {
+        let tag = TcpTag::Raw {
+            id: 0x0e,
+            payload: vec![1, 2, 3],
+        };
+
+        assert_eq!(tag.construct().unwrap(), &[0x00, 0x04, 0x0e, 1, 2, 3]);
+
+        let oversized = TcpTag::Raw {
+            id: 0x0e,
+            payload: vec![0; u16::MAX as usize],
+        };
+        assert!(oversized.construct().is_err());
+
+        let largest = TcpTag::Raw {
+            id: 0x0e,
+            payload: vec![0; u16::MAX as usize - 1],
+        };
+        assert_eq!(&largest.construct().unwrap()[..3], &[0xff, 0xff, 0x0e]);
+    }
+}
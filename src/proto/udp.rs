@@ -1,12 +1,13 @@
 use crate::proto::udp::inbound::UdpResponsePacket;
-use crate::proto::udp::outbound::UdpControlPacket;
 use bytes::{Buf, BytesMut};
-use tokio_util::codec::{Decoder, Encoder};
+use tokio_util::codec::Decoder;
 
 pub mod inbound;
 pub mod outbound;
 
-/// The tokio codec for UDP traffic to and from the roboRIO
+/// The tokio codec for UDP traffic from the roboRIO
+///
+/// Control packets are encoded directly into a reused buffer by `SendState::encode_control` instead.
 pub struct DsUdpCodec;
 
 impl Decoder for DsUdpCodec {
@@ -29,14 +30,3 @@ impl Decoder for DsUdpCodec {
         }
     }
 }
-
-impl Encoder for DsUdpCodec {
-    type Item = UdpControlPacket;
-    type Error = failure::Error;
-
-    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        dst.extend(item.encode().iter());
-
-        Ok(())
-    }
-}
@@ -1,11 +1,15 @@
 pub mod types;
 
+use self::types::tags::{
+    CanMetrics, CpuInfo, DiskInfo, JoystickOutput, PdpLog, RamInfo, UnknownTag,
+};
 use self::types::*;
 
 use crate::ext::BufExt;
 use crate::Result;
 
 use bytes::Buf;
+use std::io::{Error, ErrorKind};
 
 /// Response packet sent by the RIO over UDP every ~20ms.
 #[derive(Debug)]
@@ -15,6 +19,14 @@ pub struct UdpResponsePacket {
     pub trace: Trace,
     pub battery: f32,
     pub need_date: bool,
+    pub pdp_log: Option<PdpLog>,
+    pub disk_info: Option<DiskInfo>,
+    pub cpu_info: Option<CpuInfo>,
+    pub ram_info: Option<RamInfo>,
+    pub can_metrics: Option<CanMetrics>,
+    pub joystick_outputs: Vec<JoystickOutput>,
+    /// Tags with ids that this crate doesn't decode, or that failed to decode
+    pub unknown_tags: Vec<UnknownTag>,
 }
 
 impl UdpResponsePacket {
@@ -28,8 +40,9 @@ impl UdpResponsePacket {
         buf.read_u8()?; // Get rid of comm version
         len += 1;
 
-        let status = Status::from_bits(buf.read_u8()?).unwrap();
-        let trace = Trace::from_bits(buf.read_u8()?).unwrap();
+        // Bits this crate doesn't know about are dropped rather than rejecting the packet
+        let status = Status::from_bits_truncate(buf.read_u8()?);
+        let trace = Trace::from_bits_truncate(buf.read_u8()?);
         len += 2;
 
         let battery = {
@@ -42,39 +55,62 @@ impl UdpResponsePacket {
         let need_date = buf.read_u8()? == 1;
         len += 1;
 
+        let mut pdp_log = None;
+        let mut disk_info = None;
+        let mut cpu_info = None;
+        let mut ram_info = None;
+        let mut can_metrics = None;
+        let mut joystick_outputs = Vec::new();
+
+        let mut unknown_tags = Vec::new();
+
         use crate::util::InboundTag;
-        while let Ok(tag_id) = buf.read_u8() {
-            len += 1;
-            match tag_id {
-                0x01 => {
-                    types::tags::JoystickOutput::chomp(buf)?;
-                    len += 8;
-                }
-                0x04 => {
-                    types::tags::DiskInfo::chomp(buf)?;
-                    len += 4;
-                }
-                0x05 => {
-                    types::tags::CPUInfo::chomp(buf)?;
-                    len += 20;
-                }
-                0x06 => {
-                    types::tags::RAMInfo::chomp(buf)?;
-                    len += 8;
-                }
-                0x08 => {
-                    types::tags::PDPLog::chomp(buf)?;
-                    len += 25;
-                }
-                0x09 => {
-                    types::tags::Unknown::chomp(buf)?;
-                    len += 9;
-                }
-                0x0e => {
-                    types::tags::CANMetrics::chomp(buf)?;
-                    len += 14;
-                }
-                _ => {}
+        // Each tag is prefixed with its size, which includes the id but not the size byte itself
+        while buf.has_remaining() {
+            let size = buf.read_u8()? as usize;
+            if buf.remaining() < size {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "Tag extends past packet").into());
+            }
+            len += 1 + size;
+
+            // Tags are decoded from their own buffer so that a tag of unexpected size can't desync the rest of the packet
+            let mut tag = vec![0; size];
+            buf.copy_to_slice(&mut tag[..]);
+            let mut tag = &tag[..];
+            let id = match tag.read_u8() {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+
+            // A tag that fails to decode, such as one from a device with a layout this crate doesn't know, is kept as
+            // unknown rather than discarding the status and battery voltage in the rest of the packet
+            let payload = tag;
+            let decoded = match id {
+                0x01 => JoystickOutput::chomp(&mut tag)
+                    .map(|output| joystick_outputs.push(output))
+                    .is_ok(),
+                0x04 => DiskInfo::chomp(&mut tag)
+                    .map(|info| disk_info = Some(info))
+                    .is_ok(),
+                0x05 => CpuInfo::chomp(&mut tag)
+                    .map(|info| cpu_info = Some(info))
+                    .is_ok(),
+                0x06 => RamInfo::chomp(&mut tag)
+                    .map(|info| ram_info = Some(info))
+                    .is_ok(),
+                0x08 => PdpLog::chomp(&mut tag)
+                    .map(|log| pdp_log = Some(log))
+                    .is_ok(),
+                0x0e => CanMetrics::chomp(&mut tag)
+                    .map(|metrics| can_metrics = Some(metrics))
+                    .is_ok(),
+                _ => false,
+            };
+            if !decoded {
+                unknown_tags.push(UnknownTag {
+                    id,
+                    payload: payload.to_vec(),
+                });
             }
         }
 
@@ -85,8 +121,88 @@ impl UdpResponsePacket {
                 trace,
                 battery,
                 need_date,
+                pdp_log,
+                disk_info,
+                cpu_info,
+                ram_info,
+                can_metrics,
+                joystick_outputs,
+                unknown_tags,
             },
             len,
         ))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_tags() {
+        let data = [
+            0x00, 0x2a, 0x01, 0x04, 0x20, 0x0c, 0x80, 0x00, // Header
+            0x05, 0x04, 0x00, 0x10, 0x00, 0x00, // Disk info
+            0x03, 0x42, 0xbe, 0xef, // Unknown
+        ];
+
+        let (packet, len) = UdpResponsePacket::decode(&mut &data[..]).unwrap();
+        assert_eq!(len, data.len());
+        assert_eq!(packet.seqnum, 42);
+        assert_eq!(packet.battery, 12.5);
+        assert_eq!(packet.disk_info.unwrap().free_space, 0x0010_0000);
+        assert!(packet.cpu_info.is_none());
+        assert_eq!(
+            packet.unknown_tags,
+            vec![UnknownTag {
+                id: 0x42,
+                payload: vec![0xbe, 0xef],
+            }]
+        );
+    }
+
+    #[test]
+    fn keep_undecodable_tags() {
+        let data = [
+            0x00, 0x2a, 0x01, 0x04, 0x20, 0x0c, 0x80, 0x00, // Header
+            0x04, 0x08, 0x01, 0x02, 0x03, // Power distribution log of an unknown length
+            0x05, 0x04, 0x00, 0x10, 0x00, 0x00, // Disk info
+        ];
+
+        let (packet, len) = UdpResponsePacket::decode(&mut &data[..]).unwrap();
+        assert_eq!(len, data.len());
+        assert_eq!(packet.battery, 12.5);
+        assert!(packet.pdp_log.is_none());
+        assert_eq!(packet.disk_info.unwrap().free_space, 0x0010_0000);
+        assert_eq!(
+            packet.unknown_tags,
+            vec![UnknownTag {
+                id: 0x08,
+                payload: vec![0x01, 0x02, 0x03],
+            }]
+        );
+    }
+
+    #[test]
+    fn decode_joystick_outputs_per_port() {
+        let data = [
+            0x00, 0x2a, 0x01, 0x04, 0x20, 0x0c, 0x80, 0x00, // Header
+            0x09, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, // Port 0
+            0x09, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Port 1
+            0x09, 0x01, 0x00, 0x00, 0x00, 0x02, 0x40, 0x00, 0x80, 0x00, // Port 2
+        ];
+
+        let (packet, len) = UdpResponsePacket::decode(&mut &data[..]).unwrap();
+        assert_eq!(len, data.len());
+
+        let outputs = packet.joystick_outputs;
+        assert_eq!(outputs.len(), 3);
+        assert!(outputs[0].output(1));
+        assert!(!outputs[0].output(2));
+        assert_eq!(outputs[1].outputs, 0);
+        assert!(!outputs[2].output(1));
+        assert!(outputs[2].output(2));
+        assert_eq!(outputs[2].left_rumble, 0x4000);
+        assert_eq!(outputs[2].right_rumble, 0x8000);
+    }
+}
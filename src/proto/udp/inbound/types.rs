@@ -7,14 +7,17 @@ bitflags! {
         const CODE_START = 0b0000_1000;
         const ENABLED = 0b0000_0100;
 
-        // Mode flags
-        const TELEOP = 0b00;
+        // Mode flags, teleop is reported with neither bit set
         const TEST = 0b01;
         const AUTO = 0b10;
     }
 }
 
 impl Status {
+    pub fn enabled(self) -> bool {
+        self.contains(Status::ENABLED)
+    }
+
     pub fn is_browning_out(self) -> bool {
         self.contains(Status::BROWNOUT)
     }
@@ -1,29 +1,388 @@
 use crate::ext::BufExt;
+use crate::proto::DecodeError;
 use crate::util::InboundTag;
 use crate::Result;
 use bytes::Buf;
 
-macro_rules! gen_stub_tags {
-    ($($struct_name:ident : $num_bytes:expr),*) => {
-        $(
-        pub(crate) struct $struct_name {
-            _data: [u8; $num_bytes]
+/// A tag with an id that this crate doesn't decode, or that failed to decode, kept so that it can be inspected by users
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownTag {
+    pub id: u8,
+    /// The contents of the tag, excluding its size and id
+    pub payload: Vec<u8>,
+}
+
+/// HID outputs and rumble requested by robot code for a single joystick
+///
+/// The roboRIO sends one of these for each joystick port, in the same order that the joysticks are sent to it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct JoystickOutput {
+    /// Bitmask of the HID outputs, such as indicator LEDs. Bit `n` corresponds to output `n + 1`
+    pub outputs: u32,
+    /// The strength of the left rumble motor, ranging from `0..=65535`
+    pub left_rumble: u16,
+    /// The strength of the right rumble motor, ranging from `0..=65535`
+    pub right_rumble: u16,
+}
+
+impl JoystickOutput {
+    /// Returns whether robot code has turned on the given output. Outputs are numbered starting at 1
+    pub fn output(&self, id: u8) -> bool {
+        (1..=32).contains(&id) && self.outputs & (1 << (id - 1)) != 0
+    }
+}
+
+impl InboundTag for JoystickOutput {
+    fn chomp(buf: &mut impl Buf) -> Result<Self> {
+        let outputs = buf.read_u32_be()?;
+        let left_rumble = buf.read_u16_be()?;
+        let right_rumble = buf.read_u16_be()?;
+
+        Ok(JoystickOutput {
+            outputs,
+            left_rumble,
+            right_rumble,
+        })
+    }
+}
+
+/// The kind of power distribution device that a `PdpLog` was reported by
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PowerDistributionType {
+    /// The CTRE Power Distribution Panel, with 16 channels
+    Ctre,
+    /// The REV Power Distribution Hub, with 24 channels and a switchable channel
+    Rev,
+}
+
+impl PowerDistributionType {
+    /// Returns the number of channels that the device reports currents for
+    pub fn channels(self) -> usize {
+        match self {
+            PowerDistributionType::Ctre => 16,
+            PowerDistributionType::Rev => 24,
         }
+    }
+}
 
-        impl InboundTag for $struct_name {
-            fn chomp(buf: &mut impl Buf) -> Result<Self> {
-                let mut _data = [0; $num_bytes];
+/// Telemetry from the robot's power distribution device, reported by the roboRIO every few packets
+#[derive(Debug, Clone, PartialEq)]
+pub struct PdpLog {
+    /// The device that reported this telemetry
+    pub device: PowerDistributionType,
+    /// The current drawn by each channel, in amps. There are 16 channels on a CTRE PDP and 24 on a REV PDH
+    pub currents: Vec<f32>,
+    /// The input voltage measured by the device, in volts
+    pub voltage: f32,
+    /// The temperature of the device, in degrees celsius
+    pub temperature: f32,
+    /// Whether the switchable channel is turned on, `None` for devices without one
+    pub switchable_channel: Option<bool>,
+}
 
-                for i in 0..$num_bytes {
-                    _data[i] = buf.read_u8()?;
-                }
+impl PdpLog {
+    /// Returns the sum of the currents drawn by all channels, in amps
+    pub fn total_current(&self) -> f32 {
+        self.currents.iter().sum()
+    }
+}
 
-                Ok($struct_name { _data })
-            }
+impl InboundTag for PdpLog {
+    /// Decodes the tag, determining the type of device from its size
+    ///
+    /// The CTRE layout is 1 unknown byte, the payloads of the 3 PDP status frames on the CAN bus back to back, then
+    /// 3 bytes for the battery resistance, voltage and temperature. The REV layout widens this to 4 status frames for
+    /// its 24 channels, and replaces the battery resistance with the state of the switchable channel.
+    fn chomp(buf: &mut impl Buf) -> Result<Self> {
+        let device = match buf.remaining() {
+            25 => PowerDistributionType::Ctre,
+            36 => PowerDistributionType::Rev,
+            len => return Err(DecodeError::InvalidLength { id: 0x08, len }.into()),
+        };
+
+        let mut data = vec![0; buf.remaining()];
+        buf.copy_to_slice(&mut data[..]);
+
+        // Status frames hold 6 channels in 8 bytes, with the last frame only as long as its channels need
+        let mut currents = vec![0f32; device.channels()];
+        let mut offset = 1;
+        for frame in currents.chunks_mut(6) {
+            let len = if frame.len() == 6 {
+                8
+            } else {
+                (frame.len() * 10).div_ceil(8)
+            };
+            unpack_currents(&data[offset..offset + len], frame);
+            offset += len;
         }
-        )*
+
+        let switchable_channel = match device {
+            // The PDP's estimate of the battery's internal resistance isn't used.
+            PowerDistributionType::Ctre => None,
+            PowerDistributionType::Rev => Some(data[offset] & 0x01 != 0),
+        };
+        let voltage = f32::from(data[offset + 1]) * 0.05 + 4.0;
+        let temperature = f32::from(data[offset + 2]) * 1.032_508_4 - 67.856_45;
+
+        Ok(PdpLog {
+            device,
+            currents,
+            voltage,
+            temperature,
+            switchable_channel,
+        })
     }
 }
 
-// UDP tags should be eaten to ensure the pipe doesn't get clogged, but for now proper structs aren't implemented.
-gen_stub_tags!(PDPLog : 25, JoystickOutput : 8, DiskInfo : 4, CPUInfo : 20, RAMInfo : 8, Unknown : 9, CANMetrics : 14);
+/// Free disk space on the roboRIO
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DiskInfo {
+    /// The free space on the roboRIO's filesystem, in bytes
+    pub free_space: u32,
+}
+
+impl InboundTag for DiskInfo {
+    fn chomp(buf: &mut impl Buf) -> Result<Self> {
+        let free_space = buf.read_u32_be()?;
+
+        Ok(DiskInfo { free_space })
+    }
+}
+
+/// Memory usage of the roboRIO
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RamInfo {
+    /// The size of the largest free block of memory, in bytes
+    pub block: u32,
+    /// The total free memory, in bytes
+    pub free_space: u32,
+}
+
+impl InboundTag for RamInfo {
+    fn chomp(buf: &mut impl Buf) -> Result<Self> {
+        let block = buf.read_u32_be()?;
+        let free_space = buf.read_u32_be()?;
+
+        Ok(RamInfo { block, free_space })
+    }
+}
+
+/// CPU usage of the roboRIO, reported per core
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuInfo {
+    pub cores: Vec<CpuUsage>,
+}
+
+/// The usage of a single CPU core, split by the priority of the threads using it
+///
+/// All values are percentages ranging from `0.0..=100.0`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CpuUsage {
+    pub time_critical: f32,
+    pub above_normal: f32,
+    pub normal: f32,
+    pub low: f32,
+}
+
+impl CpuUsage {
+    /// Returns the total usage of this core across all priorities
+    pub fn total(&self) -> f32 {
+        self.time_critical + self.above_normal + self.normal + self.low
+    }
+}
+
+impl InboundTag for CpuInfo {
+    fn chomp(buf: &mut impl Buf) -> Result<Self> {
+        let num_cpus = buf.read_f32_be()? as usize;
+
+        let mut cores = Vec::new();
+        for _ in 0..num_cpus {
+            cores.push(CpuUsage {
+                time_critical: buf.read_f32_be()?,
+                above_normal: buf.read_f32_be()?,
+                normal: buf.read_f32_be()?,
+                low: buf.read_f32_be()?,
+            });
+        }
+
+        Ok(CpuInfo { cores })
+    }
+}
+
+/// Health metrics of the roboRIO's CAN bus
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CanMetrics {
+    /// The percentage of the bus' bandwidth in use, ranging from `0.0..=100.0`
+    pub utilization: f32,
+    /// The number of times the CAN controller has entered the bus off state
+    pub bus_off: u32,
+    /// The number of times the transmit buffer was full when a frame was sent
+    pub tx_full: u32,
+    /// The current receive error counter of the CAN controller
+    pub rx_errors: u8,
+    /// The current transmit error counter of the CAN controller
+    pub tx_errors: u8,
+}
+
+impl InboundTag for CanMetrics {
+    fn chomp(buf: &mut impl Buf) -> Result<Self> {
+        let utilization = buf.read_f32_be()?;
+        let bus_off = buf.read_u32_be()?;
+        let tx_full = buf.read_u32_be()?;
+        let rx_errors = buf.read_u8()?;
+        let tx_errors = buf.read_u8()?;
+
+        Ok(CanMetrics {
+            utilization,
+            bus_off,
+            tx_full,
+            rx_errors,
+            tx_errors,
+        })
+    }
+}
+
+/// Unpacks 10 bit channel currents, laid out MSB first in `packed`, into `out`
+fn unpack_currents(packed: &[u8], out: &mut [f32]) {
+    for (i, current) in out.iter_mut().enumerate() {
+        let bit = i * 10;
+        let byte = bit / 8;
+        let window = u16::from(packed[byte]) << 8 | u16::from(packed[byte + 1]);
+        let raw = (window >> (6 - bit % 8)) & 0x3ff;
+
+        *current = f32::from(raw) * 0.125;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_pdp_log() {
+        // Channel 0 draws 1A (raw 8), channel 5 draws 127.875A (raw 1023), channel 15 draws 10A (raw 80)
+        let mut data = [0u8; 25];
+        data[1] = 0b0000_0010;
+        data[7] = 0b0011_1111;
+        data[8] = 0b1111_0000;
+        data[21] = 80;
+        data[23] = 160;
+        data[24] = 66;
+
+        let log = PdpLog::chomp(&mut &data[..]).unwrap();
+        assert_eq!(log.device, PowerDistributionType::Ctre);
+        assert_eq!(log.currents.len(), 16);
+        assert_eq!(log.switchable_channel, None);
+        assert_eq!(log.currents[0], 1.0);
+        assert_eq!(log.currents[5], 127.875);
+        assert_eq!(log.currents[6], 0.0);
+        assert_eq!(log.currents[15], 10.0);
+        assert_eq!(log.total_current(), 138.875);
+        assert!((log.voltage - 12.0).abs() < 0.001);
+        assert!(log.temperature.abs() < 0.5);
+    }
+
+    #[test]
+    fn decode_pdh_log() {
+        // Channel 18 draws 1A (raw 8), channel 23 draws 127.875A (raw 1023), switchable channel is on
+        let mut data = [0u8; 36];
+        data[25] = 0b0000_0010;
+        data[31] = 0b0011_1111;
+        data[32] = 0b1111_0000;
+        data[33] = 0x01;
+        data[34] = 160;
+
+        let log = PdpLog::chomp(&mut &data[..]).unwrap();
+        assert_eq!(log.device, PowerDistributionType::Rev);
+        assert_eq!(log.currents.len(), 24);
+        assert_eq!(log.currents[18], 1.0);
+        assert_eq!(log.currents[23], 127.875);
+        assert_eq!(log.total_current(), 128.875);
+        assert_eq!(log.switchable_channel, Some(true));
+        assert!((log.voltage - 12.0).abs() < 0.001);
+
+        assert!(PdpLog::chomp(&mut &data[..30]).is_err());
+    }
+
+    #[test]
+    fn decode_cpu_info() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&2f32.to_be_bytes());
+        for usage in &[1.5f32, 2.0, 30.0, 0.5, 0.0, 0.0, 12.25, 4.0] {
+            data.extend_from_slice(&usage.to_be_bytes());
+        }
+        assert_eq!(data.len(), 4 + 2 * 16);
+
+        let info = CpuInfo::chomp(&mut &data[..]).unwrap();
+        assert_eq!(info.cores.len(), 2);
+        assert_eq!(
+            info.cores[0],
+            CpuUsage {
+                time_critical: 1.5,
+                above_normal: 2.0,
+                normal: 30.0,
+                low: 0.5,
+            }
+        );
+        assert_eq!(info.cores[0].total(), 34.0);
+        assert_eq!(info.cores[1].total(), 16.25);
+
+        // The count claims a second core that was cut off partway through
+        assert!(CpuInfo::chomp(&mut &data[..28]).is_err());
+    }
+
+    #[test]
+    fn decode_ram_and_disk_info() {
+        let data = [0x00, 0x10, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00];
+        let ram = RamInfo::chomp(&mut &data[..]).unwrap();
+        assert_eq!(ram.block, 0x0010_0000);
+        assert_eq!(ram.free_space, 0x0200_0000);
+        assert!(RamInfo::chomp(&mut &data[..6]).is_err());
+
+        let disk = DiskInfo::chomp(&mut &data[4..]).unwrap();
+        assert_eq!(disk.free_space, 0x0200_0000);
+        assert!(DiskInfo::chomp(&mut &data[..3]).is_err());
+    }
+
+    #[test]
+    fn decode_can_metrics() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&42.5f32.to_be_bytes());
+        data.extend_from_slice(&[0x00, 0x00, 0x01, 0x02]);
+        data.extend_from_slice(&[0x00, 0x00, 0x00, 0x07]);
+        data.extend_from_slice(&[3, 128]);
+
+        let metrics = CanMetrics::chomp(&mut &data[..]).unwrap();
+        assert_eq!(
+            metrics,
+            CanMetrics {
+                utilization: 42.5,
+                bus_off: 258,
+                tx_full: 7,
+                rx_errors: 3,
+                tx_errors: 128,
+            }
+        );
+
+        assert!(CanMetrics::chomp(&mut &data[..13]).is_err());
+    }
+
+    #[test]
+    fn decode_joystick_output() {
+        let data = [0x80, 0x00, 0x00, 0x05, 0xff, 0xff, 0x12, 0x34];
+
+        let output = JoystickOutput::chomp(&mut &data[..]).unwrap();
+        assert_eq!(output.outputs, 0x8000_0005);
+        assert_eq!(output.left_rumble, 65535);
+        assert_eq!(output.right_rumble, 0x1234);
+
+        assert!(output.output(1));
+        assert!(!output.output(2));
+        assert!(output.output(3));
+        assert!(output.output(32));
+        assert!(!output.output(0));
+        assert!(!output.output(33));
+
+        assert!(JoystickOutput::chomp(&mut &data[..6]).is_err());
+    }
+}
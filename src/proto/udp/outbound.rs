@@ -2,35 +2,32 @@ pub mod types;
 
 use self::types::tags::*;
 use self::types::*;
-use byteorder::{BigEndian, WriteBytesExt};
+use bytes::{BufMut, BytesMut};
 
 /// UDP control packet to send to the roboRIO
-pub struct UdpControlPacket {
+pub struct UdpControlPacket<'a> {
     pub(crate) seqnum: u16,
     pub(crate) control: Control,
     pub(crate) request: Option<Request>,
     pub(crate) alliance: Alliance,
-    pub(crate) tags: Vec<Box<dyn Tag>>,
+    pub(crate) tags: &'a [UdpTag],
 }
 
-impl UdpControlPacket {
-    /// Encodes the current state of the packet into a vec to send to the roboRIO
-    pub fn encode(&self) -> Vec<u8> {
-        let mut buf = vec![];
-        buf.write_u16::<BigEndian>(self.seqnum).unwrap();
-        buf.push(0x01); // comm version
-        buf.push(self.control.bits());
+impl UdpControlPacket<'_> {
+    /// Writes the packet to the end of `buf`
+    pub fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u16(self.seqnum);
+        buf.put_u8(0x01); // comm version
+        buf.put_u8(self.control.bits());
         match &self.request {
-            Some(ref req) => buf.push(req.bits()),
-            None => buf.push(0),
+            Some(ref req) => buf.put_u8(req.bits()),
+            None => buf.put_u8(0),
         }
 
-        buf.push(self.alliance.0);
+        buf.put_u8(self.alliance.0);
 
-        for tag in &self.tags {
-            buf.extend(tag.construct());
+        for tag in self.tags {
+            tag.construct(buf);
         }
-
-        buf
     }
 }
@@ -1,9 +1,15 @@
 //! This module contains various tags that can be attached to the outbound UDP packet
 //! The `Tag` trait contains the core logic, and is inherited by structs with specific roles
 
-use byteorder::{BigEndian, WriteBytesExt};
+use crate::util::truncate;
+use bytes::{BufMut, BytesMut};
+use std::convert::TryFrom;
+use std::fmt::Debug;
 
-use crate::util::to_u8_vec;
+/// The most axes, buttons and POVs that the roboRIO accepts from a single joystick
+pub(crate) const MAX_AXES: usize = 12;
+pub(crate) const MAX_BUTTONS: usize = 32;
+pub(crate) const MAX_POVS: usize = 4;
 
 /// Enum wrapping possible outgoing UDP tags
 #[derive(Clone, Debug)]
@@ -16,21 +22,65 @@ pub enum UdpTag {
     DateTime(DateTime),
     /// Tag sent to update the roboRIO timezone. Sent alongside the DateTime tag
     Timezone(Timezone),
+    /// A user defined tag, for tags that aren't otherwise supported by this crate
+    Custom(Box<dyn Tag>),
 }
 
-/// Represents an outgoing UDP tag
-pub(crate) trait Tag: Send {
-    fn id(&self) -> usize;
+impl UdpTag {
+    /// Writes the tag, prefixed by its size, to the end of `buf`
+    pub(crate) fn construct(&self, buf: &mut BytesMut) {
+        match self {
+            UdpTag::Countdown(cnt) => cnt.construct(buf),
+            UdpTag::Joysticks(joy) => joy.construct(buf),
+            UdpTag::DateTime(dt) => dt.construct(buf),
+            UdpTag::Timezone(tz) => tz.construct(buf),
+            UdpTag::Custom(tag) => tag.construct(buf),
+        }
+    }
+}
 
-    fn data(&self) -> Vec<u8>;
+/// Represents an outgoing UDP tag
+///
+/// This can be implemented to send tags that aren't built into the crate with `UdpTag::Custom`.
+/// The size prefix and id are written when the tag is constructed, and should not be written by `data()`.
+/// The size is a single byte, so tags with more than 254 bytes of data are left out of the packet.
+pub trait Tag: Send + Debug + TagClone {
+    /// The id of the tag, written after its size
+    fn id(&self) -> u8;
+
+    /// Writes the payload of the tag to the end of `buf`
+    fn data(&self, buf: &mut BytesMut);
+
+    /// Writes the tag, prefixed by its size, to the end of `buf`
+    fn construct(&self, buf: &mut BytesMut) {
+        let start = buf.len();
+        // The size is filled in once the length of the data is known
+        buf.put_u8(0);
+        buf.put_u8(self.id());
+        self.data(buf);
+
+        // A size that doesn't fit would desync every tag after this one, so the tag is dropped instead
+        match u8::try_from(buf.len() - start - 1) {
+            Ok(size) => buf[start] = size,
+            Err(_) => buf.truncate(start),
+        }
+    }
+}
 
-    fn construct(&self) -> Vec<u8> {
-        let mut buf = vec![self.id() as u8];
-        buf.extend(self.data());
+/// Allows boxed tags to be cloned. This is implemented for all tags that implement `Clone`
+pub trait TagClone {
+    fn clone_box(&self) -> Box<dyn Tag>;
+}
 
-        buf.insert(0, buf.len() as u8);
+impl<T: Tag + Clone + 'static> TagClone for T {
+    fn clone_box(&self) -> Box<dyn Tag> {
+        Box::new(self.clone())
+    }
+}
 
-        buf
+impl Clone for Box<dyn Tag> {
+    fn clone(&self) -> Box<dyn Tag> {
+        self.clone_box()
     }
 }
 
@@ -49,60 +99,121 @@ impl Countdown {
 }
 
 impl Tag for Countdown {
-    fn id(&self) -> usize {
+    fn id(&self) -> u8 {
         0x07
     }
 
-    fn data(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(2);
-        buf.write_f32::<BigEndian>(self.seconds_remaining).unwrap();
-
-        buf
+    fn data(&self, buf: &mut BytesMut) {
+        buf.put_f32(self.seconds_remaining);
     }
 }
 
 /// Tag containing values from joysticks
+///
+/// Values are stored inline so that the tag can be built every packet without allocating.
 #[derive(Clone, Debug)]
 pub struct Joysticks {
-    axes: Vec<i8>,
-    buttons: Vec<bool>,
-    povs: Vec<i16>,
+    axes: [i8; MAX_AXES],
+    axis_count: u8,
+    /// Button states, with button 1 in the least significant bit
+    buttons: u32,
+    button_count: u8,
+    povs: [i16; MAX_POVS],
+    pov_count: u8,
 }
 
 impl Joysticks {
+    /// Creates a joystick tag from the given values
+    ///
+    /// Values past the 12 axes, 32 buttons and 4 POVs that the roboRIO accepts are dropped.
     pub fn new(axes: Vec<i8>, buttons: Vec<bool>, povs: Vec<i16>) -> Joysticks {
+        let mut joysticks = Joysticks::with_counts(0, 0, 0);
+
+        for (id, value) in axes.into_iter().enumerate().take(MAX_AXES) {
+            joysticks.set_axis(id, value);
+        }
+        for (id, pressed) in buttons.into_iter().enumerate().take(MAX_BUTTONS) {
+            joysticks.set_button(id + 1, pressed);
+        }
+        for (id, angle) in povs.into_iter().enumerate().take(MAX_POVS) {
+            joysticks.set_pov(id, angle);
+        }
+
+        joysticks
+    }
+
+    /// Creates a joystick with the given number of centered axes, released buttons and unpressed POVs
+    pub(crate) fn with_counts(axes: usize, buttons: usize, povs: usize) -> Joysticks {
         Joysticks {
-            axes,
-            buttons,
-            povs,
+            axes: [0; MAX_AXES],
+            axis_count: axes.min(MAX_AXES) as u8,
+            buttons: 0,
+            button_count: buttons.min(MAX_BUTTONS) as u8,
+            povs: [-1; MAX_POVS],
+            pov_count: povs.min(MAX_POVS) as u8,
         }
     }
-}
 
-impl Tag for Joysticks {
-    fn id(&self) -> usize {
-        0x0c
+    /// Returns the number of axes, buttons and POVs that the joystick is sent with
+    pub(crate) fn counts(&self) -> (usize, usize, usize) {
+        (
+            self.axis_count as usize,
+            self.button_count as usize,
+            self.pov_count as usize,
+        )
     }
 
-    fn data(&self) -> Vec<u8> {
-        let mut buf = vec![];
-        buf.write_u8(self.axes.len() as u8).unwrap();
-        for axis in &self.axes {
-            buf.write_i8(*axis).unwrap();
+    /// Sets the axis with the given 0-based id, growing the joystick to fit it. Ids past 11 are ignored
+    pub(crate) fn set_axis(&mut self, id: usize, value: i8) {
+        if id < MAX_AXES {
+            self.axes[id] = value;
+            self.axis_count = self.axis_count.max(id as u8 + 1);
         }
+    }
 
-        let buttons = to_u8_vec(&self.buttons);
+    /// Sets the button with the given 1-based id, growing the joystick to fit it. Ids outside of 1-32 are ignored
+    pub(crate) fn set_button(&mut self, id: usize, pressed: bool) {
+        if (1..=MAX_BUTTONS).contains(&id) {
+            let mask = 1 << (id - 1);
+            if pressed {
+                self.buttons |= mask;
+            } else {
+                self.buttons &= !mask;
+            }
+            self.button_count = self.button_count.max(id as u8);
+        }
+    }
 
-        buf.push(10);
-        buf.extend(buttons);
+    /// Sets the POV with the given 0-based id, growing the joystick to fit it. Ids past 3 are ignored
+    pub(crate) fn set_pov(&mut self, id: usize, angle: i16) {
+        if id < MAX_POVS {
+            self.povs[id] = angle;
+            self.pov_count = self.pov_count.max(id as u8 + 1);
+        }
+    }
+}
 
-        buf.push(self.povs.len() as u8);
+impl Tag for Joysticks {
+    fn id(&self) -> u8 {
+        0x0c
+    }
+
+    fn data(&self, buf: &mut BytesMut) {
+        buf.put_u8(self.axis_count);
+        for axis in &self.axes[..self.axis_count as usize] {
+            buf.put_i8(*axis);
+        }
 
-        for pov in &self.povs {
-            buf.write_i16::<BigEndian>(*pov).unwrap();
+        // Buttons are packed into the fewest bytes that fit them, most significant byte first
+        buf.put_u8(self.button_count);
+        for byte in (0..(self.button_count as u32).div_ceil(8)).rev() {
+            buf.put_u8((self.buttons >> (byte * 8)) as u8);
         }
 
-        buf
+        buf.put_u8(self.pov_count);
+        for pov in &self.povs[..self.pov_count as usize] {
+            buf.put_i16(*pov);
+        }
     }
 }
 
@@ -141,46 +252,43 @@ impl DateTime {
 }
 
 impl Tag for DateTime {
-    fn id(&self) -> usize {
+    fn id(&self) -> u8 {
         0x0f
     }
 
-    fn data(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
-        buf.write_u32::<BigEndian>(self.micros).unwrap();
-        buf.push(self.second);
-        buf.push(self.minute);
-        buf.push(self.hour);
-        buf.push(self.day);
-        buf.push(self.month);
-        buf.push(self.year);
-
-        buf
+    fn data(&self, buf: &mut BytesMut) {
+        buf.put_u32(self.micros);
+        buf.put_u8(self.second);
+        buf.put_u8(self.minute);
+        buf.put_u8(self.hour);
+        buf.put_u8(self.day);
+        buf.put_u8(self.month);
+        buf.put_u8(self.year);
     }
 }
 
-/// Tag containing the current timezone of the RIO
+/// Tag containing the current timezone of the RIO, as an IANA timezone name such as `America/Toronto`
 #[derive(Clone, Debug)]
 pub struct Timezone {
     tz: String,
 }
 
 impl Timezone {
+    /// Creates a timezone tag, truncating `tz` to the 254 bytes that fit in a tag
     pub fn new(tz: &str) -> Timezone {
-        Timezone { tz: tz.to_string() }
+        Timezone {
+            tz: truncate(tz, 254).to_string(),
+        }
     }
 }
 
 impl Tag for Timezone {
-    fn id(&self) -> usize {
+    fn id(&self) -> u8 {
         0x10
     }
 
-    fn data(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
-        buf.extend_from_slice(self.tz.as_bytes());
-
-        buf
+    fn data(&self, buf: &mut BytesMut) {
+        buf.put_slice(self.tz.as_bytes());
     }
 }
 
@@ -193,8 +301,73 @@ mod test {
         let countdown = Countdown {
             seconds_remaining: 2f32,
         };
-        let buf = countdown.construct();
+        let mut buf = BytesMut::new();
+        countdown.construct(&mut buf);
+
+        assert_eq!(buf, &[0x05, 0x07, 0x040, 0x0, 0x0, 0x0][..]);
+    }
+
+    #[test]
+    fn encode_joysticks() {
+        let mut buttons = vec![false; 32];
+        buttons[0] = true;
+        buttons[31] = true;
+        let joysticks = Joysticks::new(vec![0; 12], buttons, vec![-1, 90, 180, 270]);
+
+        let mut expected = vec![12];
+        expected.extend_from_slice(&[0; 12]);
+        expected.extend_from_slice(&[32, 0x80, 0x00, 0x00, 0x01]);
+        expected.extend_from_slice(&[4, 0xff, 0xff, 0x00, 90, 0x00, 180, 0x01, 0x0e]);
+        let mut buf = BytesMut::new();
+        joysticks.data(&mut buf);
+        assert_eq!(buf, expected);
+    }
+
+    #[derive(Clone, Debug)]
+    struct Custom(u8);
+
+    impl Tag for Custom {
+        fn id(&self) -> u8 {
+            0x20
+        }
 
-        assert_eq!(buf, &[0x05, 0x07, 0x040, 0x0, 0x0, 0x0]);
+        fn data(&self, buf: &mut BytesMut) {
+            buf.put_slice(&[self.0, self.0]);
+        }
+    }
+
+    #[test]
+    fn encode_custom_tag() {
+        let tag = UdpTag::Custom(Box::new(Custom(0xab)));
+
+        let mut buf = BytesMut::new();
+        tag.clone().construct(&mut buf);
+        assert_eq!(buf, &[0x03, 0x20, 0xab, 0xab][..]);
+    }
+
+    #[test]
+    fn drop_oversized_tag() {
+        let mut buf = BytesMut::new();
+        UdpTag::Custom(Box::new(Oversized)).construct(&mut buf);
+        UdpTag::Custom(Box::new(Custom(0xab))).construct(&mut buf);
+        assert_eq!(buf, &[0x03, 0x20, 0xab, 0xab][..]);
+
+        let mut buf = BytesMut::new();
+        Timezone::new(&"a".repeat(300)).construct(&mut buf);
+        assert_eq!(buf.len(), 256);
+        assert_eq!(buf[0], 255);
+    }
+
+    #[derive(Clone, Debug)]
+    struct Oversized;
+
+    impl Tag for Oversized {
+        fn id(&self) -> u8 {
+            0x21
+        }
+
+        fn data(&self, buf: &mut BytesMut) {
+            buf.put_slice(&[0; 255]);
+        }
     }
 }
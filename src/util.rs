@@ -1,34 +1,5 @@
 use bytes::Buf;
 
-/// Function to translate boolean button values into the bytes that the roboRIO expects
-/// Buttons are encoded LSB 0 on the wire. This algorithm was MSB 0 originally, and I didn't feel like translating it properly
-pub(crate) fn to_u8_vec(vec_in: &[bool]) -> Vec<u8> {
-    let mut vec = Vec::new();
-
-    for i in (0..vec_in.len()).step_by(8) {
-        let mut num: u8 = 0;
-        for j in i..i + 8 {
-            num <<= 1;
-            num |= *match vec_in.get(j) {
-                Some(a) => a,
-                None => &false,
-            } as u8;
-        }
-        vec.push(reverse_byte(num));
-    }
-
-    vec.into_iter().rev().collect()
-}
-
-/// Reverses all the bits in a byte. Used to convert MSB 0 into LSB 0 for button encoding
-fn reverse_byte(mut byte: u8) -> u8 {
-    byte = (byte & 0xF0) >> 4 | (byte & 0x0F) << 4;
-    byte = (byte & 0xCC) >> 2 | (byte & 0x33) << 2;
-    byte = (byte & 0xAA) >> 1 | (byte & 0x55) << 1;
-
-    byte
-}
-
 /// Converts the given team number into a String containing the IP of the roboRIO
 /// Assumes the roboRIO will exist at 10.TE.AM.2
 pub(crate) fn ip_from_team_number(team: u32) -> String {
@@ -42,8 +13,102 @@ pub(crate) fn ip_from_team_number(team: u32) -> String {
     }
 }
 
+/// Attempts to detect the IANA name of the host's timezone, such as `America/Toronto`
+///
+/// The `TZ` environment variable is checked first, followed by `/etc/timezone` and the target of the `/etc/localtime` symlink.
+/// Windows timezones aren't detected, as Windows doesn't use IANA names, so only `TZ` is checked there.
+pub(crate) fn host_timezone() -> Option<String> {
+    use std::{env, fs};
+
+    if let Some(tz) = env::var("TZ").ok().as_deref().and_then(timezone_from_env) {
+        return Some(tz.to_string());
+    }
+
+    if let Ok(tz) = fs::read_to_string("/etc/timezone") {
+        let tz = tz.trim();
+        if !tz.is_empty() {
+            return Some(tz.to_string());
+        }
+    }
+
+    let localtime = fs::read_link("/etc/localtime").ok()?;
+    timezone_from_path(localtime.to_str()?).map(str::to_string)
+}
+
+/// Parses the IANA timezone out of the value of the `TZ` environment variable
+///
+/// Absolute paths to a zoneinfo file are accepted, while POSIX rules such as `EST5EDT` aren't, since the roboRIO can't use them.
+fn timezone_from_env(tz: &str) -> Option<&str> {
+    let tz = tz.trim_start_matches(':');
+    if tz.starts_with('/') {
+        timezone_from_path(tz)
+    } else if tz.contains('/') || tz == "UTC" {
+        Some(tz)
+    } else {
+        None
+    }
+}
+
+/// Returns the part of a path to a zoneinfo file that names the timezone, such as `America/Toronto` from
+/// `/usr/share/zoneinfo/America/Toronto`
+fn timezone_from_path(path: &str) -> Option<&str> {
+    path.find("zoneinfo/")
+        .map(|idx| &path[idx + "zoneinfo/".len()..])
+        .filter(|tz| !tz.is_empty())
+}
+
+/// Truncates `s` to at most `max` bytes, without splitting a character
+pub(crate) fn truncate(s: &str, max: usize) -> &str {
+    let mut len = s.len().min(max);
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    &s[..len]
+}
+
 pub(crate) trait InboundTag {
     fn chomp(buf: &mut impl Buf) -> crate::Result<Self>
     where
         Self: Sized;
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_tz_env() {
+        assert_eq!(
+            timezone_from_env("America/Toronto"),
+            Some("America/Toronto")
+        );
+        assert_eq!(timezone_from_env(":Europe/London"), Some("Europe/London"));
+        assert_eq!(timezone_from_env("UTC"), Some("UTC"));
+        assert_eq!(
+            timezone_from_env("/usr/share/zoneinfo/America/Toronto"),
+            Some("America/Toronto")
+        );
+        assert_eq!(
+            timezone_from_env(":/usr/share/zoneinfo/Etc/UTC"),
+            Some("Etc/UTC")
+        );
+        assert_eq!(timezone_from_env("/etc/localtime"), None);
+        assert_eq!(timezone_from_env("EST5EDT"), None);
+        assert_eq!(timezone_from_env(""), None);
+    }
+
+    #[test]
+    fn parse_localtime_link() {
+        assert_eq!(
+            timezone_from_path("../usr/share/zoneinfo/Australia/Sydney"),
+            Some("Australia/Sydney")
+        );
+        assert_eq!(
+            timezone_from_path("/var/db/timezone/zoneinfo/America/Los_Angeles"),
+            Some("America/Los_Angeles")
+        );
+        assert_eq!(timezone_from_path("/usr/share/zoneinfo/"), None);
+        assert_eq!(timezone_from_path("/etc/timezone"), None);
+    }
+}